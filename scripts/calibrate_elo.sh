@@ -0,0 +1,47 @@
+#!/usr/bin/env bash
+# UCI_Elo calibration: each strength anchor against the one below it, at fixed nodes.
+# Adjacent anchors in src/strength.rs should come out about 400 Elo apart.
+#
+# calibration/book.epd holds balanced openings from self-play after random plies:
+#   rustbot datagen dg.txt --games 400 --nodes 1000 --random-plies 8 --seed 1
+#   awk -F' \\| ' '{split($1,f," "); if (f[6]==5 && $2<40 && $2>-40) print $1}' dg.txt | sort -u
+set -euo pipefail
+
+if [[ $# -lt 1 ]]; then
+    echo "usage: $0 <engine-binary> [elo ...]" >&2
+    exit 1
+fi
+
+ENGINE="$(realpath "$1")"
+shift
+ELOS=("$@")
+if [[ ${#ELOS[@]} -eq 0 ]]; then
+    ELOS=(1000 1400 1800 2200 2600)
+fi
+ROOT="$(cd "$(dirname "$0")/.." && pwd)"
+BOOK="${BOOK:-$ROOT/calibration/book.epd}"
+ROUNDS="${ROUNDS:-150}"
+# the clock is generous so the node budget always ends the search
+TC="${TC:-60+0.6}"
+OUT_DIR="${OUT_DIR:-$ROOT/calibration}"
+mkdir -p "$OUT_DIR"
+WORK="$(mktemp -d)"
+trap 'rm -rf "$WORK"' EXIT
+
+for ELO in "${ELOS[@]}"; do
+    cat > "$WORK/elo$ELO" <<WRAPPER
+#!/bin/sh
+(printf 'setoption name UCI_LimitStrength value true\nsetoption name UCI_Elo value $ELO\n'; cat) | "$ENGINE"
+WRAPPER
+    chmod +x "$WORK/elo$ELO"
+done
+
+for ((i = 1; i < ${#ELOS[@]}; i++)); do
+    LOW="${ELOS[i - 1]}"
+    HIGH="${ELOS[i]}"
+    OUT="$OUT_DIR/elo${HIGH}_vs_elo${LOW}.out"
+    # SPRT bounds out of reach, so every round is played
+    "$ENGINE" match "$WORK/elo$HIGH" "$WORK/elo$LOW" \
+        tc "$TC" rounds "$ROUNDS" book "$BOOK" alpha 1e-300 beta 1e-300 \
+        2>&1 | tee "$OUT"
+done
@@ -0,0 +1,176 @@
+use cozy_chess::*;
+use crate::notation::to_san;
+use crate::pgn::*;
+use crate::search::*;
+use std::fs::File;
+use std::io::BufWriter;
+
+/// Loss thresholds in win-probability percentage points (same scale as lichess).
+const WIN_PROBABILITY_THRESHOLDS: [f64; 3] = [5.0, 10.0, 15.0];
+/// Loss thresholds in centipawns.
+const CENTIPAWN_THRESHOLDS: [f64; 3] = [50.0, 100.0, 300.0];
+/// Evaluations are clamped before computing losses so won positions do not dominate.
+const EVAL_CLAMP: i32 = 1000;
+
+pub struct AnalysisConfig {
+    pub limits: SearchLimits,
+    pub centipawn_thresholds: bool, //classify by centipawn loss instead of win-probability loss
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Judgement {
+    Good,
+    Inaccuracy,
+    Mistake,
+    Blunder,
+}
+
+impl Judgement {
+    fn nag(&self) -> Option<u8> {
+        match self {
+            Judgement::Good => None,
+            Judgement::Inaccuracy => Some(6),
+            Judgement::Mistake => Some(2),
+            Judgement::Blunder => Some(4),
+        }
+    }
+    fn name(&self) -> &'static str {
+        match self {
+            Judgement::Good => "Good",
+            Judgement::Inaccuracy => "Inaccuracy",
+            Judgement::Mistake => "Mistake",
+            Judgement::Blunder => "Blunder",
+        }
+    }
+}
+
+#[derive(Default)]
+struct PlayerSummary {
+    moves: u32,
+    accuracy_sum: f64,
+    centipawn_loss_sum: i64,
+    counts: [u32; 3], //inaccuracies, mistakes, blunders
+}
+
+impl PlayerSummary {
+    fn print(&self, color: &str, name: &str) {
+        let moves: f64 = self.moves.max(1) as f64;
+        println!(
+            "  {} ({}): accuracy {:.1}%, ACPL {:.0}, {} inaccuracies, {} mistakes, {} blunders",
+            color, name, self.accuracy_sum / moves, self.centipawn_loss_sum as f64 / moves,
+            self.counts[0], self.counts[1], self.counts[2]
+        );
+    }
+}
+
+/// Search result for one position, scores from the side to move's point of view.
+struct PositionEval {
+    score: i32,
+    best_move: Option<Move>,
+    lines: Vec<PvLine>,
+}
+
+fn evaluate_position(searcher: &mut AlphaBetaSearcher, board: &Board, limits: SearchLimits) -> PositionEval {
+    match board.status() {
+        GameStatus::Won => return PositionEval { score: -MATE_VALUE, best_move: None, lines: Vec::new() },
+        GameStatus::Drawn => return PositionEval { score: 0, best_move: None, lines: Vec::new() },
+        GameStatus::Ongoing => (),
+    }
+    let result: SearchResult = searcher.search(board, limits);
+    let best_move: Option<Move> = board.is_legal(result.best_move).then_some(result.best_move);
+    PositionEval { score: result.score, best_move, lines: result.lines }
+}
+
+/// Win probability in percent for a centipawn score, as used by lichess.
+fn win_probability(cp: i32) -> f64 {
+    50.0 + 50.0 * (2.0 / (1.0 + (-0.00368208 * cp as f64).exp()) - 1.0)
+}
+
+fn clamp_eval(score: i32) -> i32 {
+    score.clamp(-EVAL_CLAMP, EVAL_CLAMP)
+}
+
+fn is_mate(score: i32) -> bool {
+    score.abs() > MATE_VALUE - 1000
+}
+
+/// `[%eval]` value from White's point of view: pawns, or `#n` for mates.
+fn format_eval(white_score: i32) -> String {
+    if is_mate(white_score) {
+        let moves: i32 = (MATE_VALUE - white_score.abs() + 1) / 2;
+        return format!("#{}", if white_score > 0 { moves } else { -moves });
+    }
+    format!("{:.2}", white_score as f64 / 100.0)
+}
+
+/// Replays every game of `in_path`, annotates each move with the engine's evaluation
+/// and judgement, writes the result to `out_path` and prints per-player summaries.
+pub fn analyse_pgn(searcher: &mut AlphaBetaSearcher, in_path: &str, out_path: &str, config: &AnalysisConfig) -> std::io::Result<()> {
+    let text: String = std::fs::read_to_string(in_path)?;
+    let mut games: Vec<PgnGame> = parse_pgn(&text).map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+    let mut out: BufWriter<File> = BufWriter::new(File::create(out_path)?);
+    let thresholds: [f64; 3] = if config.centipawn_thresholds { CENTIPAWN_THRESHOLDS } else { WIN_PROBABILITY_THRESHOLDS };
+    searcher.set_verbose(false);
+    for (game_index, game) in games.iter_mut().enumerate() {
+        let mut summaries: [PlayerSummary; 2] = [PlayerSummary::default(), PlayerSummary::default()];
+        let mut board: Board = game.start.clone();
+        searcher.clear_threefold_repetition();
+        searcher.add_to_threefold_repetition(board.hash());
+        let mut before: PositionEval = evaluate_position(searcher, &board, config.limits);
+        for pgn_move in game.moves.iter_mut() {
+            let mover: Color = board.side_to_move();
+            let mut after_board: Board = board.clone();
+            after_board.play(pgn_move.mv);
+            searcher.add_to_threefold_repetition(after_board.hash());
+            let after: PositionEval = evaluate_position(searcher, &after_board, config.limits);
+
+            //prefer the MultiPV score of the played move, it comes from the same search as the best move
+            let played_score: i32 = before.lines.iter()
+                .find(|line| line.best_move == pgn_move.mv)
+                .map_or(-after.score, |line| line.score);
+            let best_score: i32 = before.score.max(played_score);
+            let cp_loss: i32 = clamp_eval(best_score) - clamp_eval(played_score);
+            let wp_loss: f64 = win_probability(clamp_eval(best_score)) - win_probability(clamp_eval(played_score));
+            let loss: f64 = if config.centipawn_thresholds { cp_loss as f64 } else { wp_loss };
+            let judgement: Judgement = match thresholds.iter().rposition(|&t| loss >= t) {
+                Some(0) => Judgement::Inaccuracy,
+                Some(1) => Judgement::Mistake,
+                Some(_) => Judgement::Blunder,
+                None => Judgement::Good,
+            };
+
+            let summary: &mut PlayerSummary = &mut summaries[mover as usize];
+            summary.moves += 1;
+            summary.centipawn_loss_sum += cp_loss as i64;
+            summary.accuracy_sum += (103.1668 * (-0.04354 * wp_loss).exp() - 3.1669).clamp(0.0, 100.0);
+
+            let white_score: i32 = if mover == Color::White { played_score } else { -played_score };
+            let mut comment: String = format!("[%eval {}]", format_eval(white_score));
+            if let Some(nag) = judgement.nag() {
+                summary.counts[judgement as usize - 1] += 1;
+                pgn_move.nags.push(nag);
+                if let Some(best_move) = before.best_move {
+                    comment.push_str(&format!(" {}. {} was best.", judgement.name(), to_san(&board, best_move)));
+                    let mut best: PgnMove = PgnMove::new(best_move);
+                    let best_white_score: i32 = if mover == Color::White { best_score } else { -best_score };
+                    best.comment = Some(format!("[%eval {}]", format_eval(best_white_score)));
+                    pgn_move.variations.insert(0, vec![best]);
+                }
+            }
+            pgn_move.comment = Some(match pgn_move.comment.take() {
+                Some(previous) => format!("{} {}", comment, previous),
+                None => comment,
+            });
+            board = after_board;
+            before = after;
+        }
+
+        println!("Game {}: {} - {} {}", game_index + 1, game.tag("White").unwrap_or("?"), game.tag("Black").unwrap_or("?"), game.result);
+        summaries[Color::White as usize].print("White", game.tag("White").unwrap_or("?"));
+        summaries[Color::Black as usize].print("Black", game.tag("Black").unwrap_or("?"));
+        write_pgn(&mut out, game)?;
+    }
+    searcher.set_verbose(true);
+    println!("Annotated {} games to {}", games.len(), out_path);
+    Ok(())
+}
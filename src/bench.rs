@@ -0,0 +1,37 @@
+use cozy_chess::*;
+use crate::search::*;
+use std::time::Duration;
+
+const BENCH_FENS: [&str; 8] = [
+    "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+    "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+    "r1bq1rk1/pp2bppp/2n1pn2/3p4/2PP4/2N1PN2/PP3PPP/R2QKB1R w KQ - 0 8",
+    "2rr3k/pp3pp1/1nnqbN1p/3pN3/2pP4/2P3Q1/PPB4P/R4RK1 w - - 0 1",
+    "r4rk1/1pp1qppp/p1np1n2/2b1p1B1/2B1P1b1/P1NP1N2/1PP1QPPP/R4RK1 w - - 0 10",
+    "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1",
+    "8/8/4k3/3p4/3P4/4K3/8/8 w - - 0 1",
+    "6k1/5ppp/8/8/8/8/5PPP/3R2K1 w - - 0 1",
+];
+
+/// Searches a fixed set of positions to a fixed depth. The node count is a
+/// functional signature of the search and the NPS a speed measurement.
+pub fn run_bench(searcher: &mut AlphaBetaSearcher, depth: i32) {
+    let mut nodes: u64 = 0;
+    let mut elapsed: Duration = Duration::ZERO;
+    #[cfg(feature = "stats")]
+    let mut stats: crate::stats::SearchStats = Default::default();
+    searcher.set_verbose(false);
+    for fen in BENCH_FENS {
+        let board: Board = Board::from_fen(fen, false).unwrap();
+        searcher.clear_threefold_repetition();
+        let result: SearchResult = searcher.search(&board, SearchLimits { depth: Some(depth), ..SearchLimits::default() });
+        nodes += result.nodes;
+        elapsed += result.elapsed;
+        #[cfg(feature = "stats")]
+        stats.merge(&result.stats);
+    }
+    searcher.set_verbose(true);
+    #[cfg(feature = "stats")]
+    println!("{}", stats.info_string());
+    println!("{} nodes {} nps", nodes, (nodes as f64 / elapsed.as_secs_f64().max(1e-9)) as u64);
+}
@@ -0,0 +1,423 @@
+//! Command line front-end, so the engine can be scripted without piping UCI text:
+//! `rustbot [--json] [<subcommand> [args]]`. Without a subcommand the engine speaks
+//! UCI on stdin. The argument parsers are shared with the matching stdin commands,
+//! which take the same flags with or without the leading dashes.
+use cozy_chess::*;
+use crate::analysis::{self, AnalysisConfig};
+use crate::bench;
+use crate::datagen::{self, DatagenConfig};
+use crate::evaluation;
+use crate::search::{AlphaBetaSearcher, InfoFormat, SearchLimits};
+use crate::spsa;
+use crate::testsuite;
+use crate::tournament::{self, MatchConfig, TimeControl};
+use crate::tunables;
+use crate::uci;
+
+/// Exit code of a command that ran and succeeded.
+pub const EXIT_OK: i32 = 0;
+/// Exit code of a command that was understood but failed, e.g. on a missing file.
+pub const EXIT_FAILURE: i32 = 1;
+/// Exit code of a command line that could not be parsed.
+pub const EXIT_USAGE: i32 = 2;
+
+pub const USAGE: &str = "\
+usage: rustbot [--json] [<command> [args]]
+
+commands:
+  uci                                  speak UCI on stdin (the default)
+  bench [--depth <n>]                  search the bench positions, print nodes and nps
+  perft <fen|startpos> <depth>         count leaf nodes, split by root move
+  search <fen|startpos> [--depth <n>] [--nodes <n>] [--movetime <ms>] [--multipv <n>]
+                                       search one position, print the best move
+  eval <fen|startpos>                  print the static evaluation term by term
+  testsuite <file.epd> [--depth <n> | --nodes <n> | --movetime <ms>] [--report <file>]
+                                       run an EPD test suite, write a CSV report
+  analyse <in.pgn> <out.pgn> [--depth <n> | --movetime <ms>] [--multipv <n>] [--cp]
+                                       annotate the games of a PGN file
+  match <dev> <baseline> [--tc <base+inc>] [--book <file>] [--concurrency <n>] [--rounds <pairs>]
+        [--pgn <file>] [--elo0 <x>] [--elo1 <x>] [--alpha <x>] [--beta <x>]
+                                       play an SPRT match between two engines
+  tune [--json]                        print the SPSA config of the tunable search parameters
+  tune <engine> [--iterations <n>] [--tc <base+inc>] [--book <file>] [--report <n>]
+                                       tune those parameters by SPSA self-play, one game pair per
+                                       iteration; <engine> must be built with --features tune
+  datagen <out> [--games <n>] [--nodes <n>] [--random-plies <n>] [--seed <n>]
+                                       write `fen | score | result` training positions from
+                                       fixed-node self-play
+  help                                 print this help
+
+--json switches search output to NDJSON.
+exit codes: 0 success, 1 the command failed, 2 invalid command line.";
+
+/// A parsed command line.
+pub enum Command {
+    Uci,
+    Help,
+    Bench(i32),
+    Perft(Board, u32),
+    Search(Board, SearchLimits),
+    Eval(Board),
+    Testsuite(TestsuiteArgs),
+    Analyse(AnalyseArgs),
+    Match(MatchConfig),
+    Tune { json: bool },
+    Spsa(MatchConfig),
+    Datagen(DatagenConfig),
+}
+
+pub struct TestsuiteArgs {
+    pub epd_path: String,
+    pub limits: SearchLimits,
+    pub report_path: String,
+}
+
+pub struct AnalyseArgs {
+    pub input: String,
+    pub output: String,
+    pub config: AnalysisConfig,
+}
+
+const SEARCH_FLAGS: [&str; 4] = ["depth", "nodes", "movetime", "multipv"];
+
+/// Parses the arguments after the program name. Returns the command and whether
+/// `--json` was given.
+pub fn parse(args: &[String]) -> Result<(Command, bool), String> {
+    let json: bool = args.iter().any(|arg| arg == "--json");
+    let words: Vec<&str> = args.iter().map(String::as_str).filter(|&arg| arg != "--json").collect();
+    let command: Command = match words.first().copied() {
+        None | Some("uci") => Command::Uci,
+        Some("help" | "--help" | "-h") => Command::Help,
+        //a bare depth is kept for scripts written against the old `bench <depth>`
+        Some("bench") if words.len() == 2 => Command::Bench(number("depth", Some(words[1]))?),
+        Some("bench") => {
+            let mut depth: i32 = 7;
+            for (flag, value) in flags(&words[1..], &[])? {
+                match flag {
+                    "depth" => depth = number(flag, value)?,
+                    _ => return Err(format!("unknown bench flag --{}", flag)),
+                }
+            }
+            Command::Bench(depth)
+        },
+        Some("perft") => match &words[1..] {
+            [fen @ .., depth] if !fen.is_empty() => match number("depth", Some(depth))? {
+                0 => return Err("perft depth must be at least 1".to_string()),
+                depth => Command::Perft(parse_board(fen)?, depth),
+            },
+            _ => return Err("perft needs a position and a depth".to_string()),
+        },
+        Some("search") => {
+            //no FEN field is a flag name, so the FEN ends at the first flag, dashed or not
+            let fen_end: usize = words.iter()
+                .position(|w| w.starts_with("--") || SEARCH_FLAGS.contains(w))
+                .unwrap_or(words.len());
+            let mut limits: SearchLimits = SearchLimits::default();
+            for (flag, value) in flags(&words[fen_end..], &[])? {
+                match flag {
+                    "depth" => limits.depth = Some(number(flag, value)?),
+                    "nodes" => limits.nodes = Some(number(flag, value)?),
+                    "movetime" => limits.movetime = Some(number(flag, value)?),
+                    "multipv" => limits.multipv = number(flag, value)?,
+                    _ => return Err(format!("unknown search flag --{}", flag)),
+                }
+            }
+            if limits.depth.is_none() && limits.nodes.is_none() && limits.movetime.is_none() {
+                limits.movetime = Some(1000);
+            }
+            Command::Search(parse_board(&words[1..fen_end])?, limits)
+        },
+        Some("eval") => Command::Eval(parse_board(&words[1..])?),
+        Some("testsuite") => Command::Testsuite(parse_testsuite(&words)?),
+        Some("analyse") => Command::Analyse(parse_analyse(&words)?),
+        Some("match") => Command::Match(parse_match(&words)?),
+        Some("tune") => match &words[1..] {
+            [] => Command::Tune { json: false },
+            ["json"] => Command::Tune { json: true },
+            _ => Command::Spsa(parse_tune(&words)?),
+        },
+        Some("datagen") => Command::Datagen(parse_datagen(&words)?),
+        Some(other) => return Err(format!("unknown command {}", other)),
+    };
+    //`tune --json` asks for the JSON config, not NDJSON search output
+    match command {
+        Command::Tune { .. } if json => Ok((Command::Tune { json: true }, false)),
+        command => Ok((command, json)),
+    }
+}
+
+/// Runs a command other than `Uci` and returns the process exit code.
+pub fn run(command: Command, searcher: &mut AlphaBetaSearcher) -> i32 {
+    let result: std::io::Result<()> = match command {
+        Command::Uci => unreachable!("uci is run by the main loop"),
+        Command::Help => {
+            println!("{}", USAGE);
+            Ok(())
+        },
+        Command::Bench(depth) => {
+            bench::run_bench(searcher, depth);
+            Ok(())
+        },
+        Command::Perft(board, depth) => {
+            let mut total: u64 = 0;
+            for m in uci::legal_moves(&board) {
+                let mut child: Board = board.clone();
+                child.play(m);
+                let nodes: u64 = perft(&child, depth - 1);
+                println!("{}: {}", AlphaBetaSearcher::to_standard_uci(&board, m), nodes);
+                total += nodes;
+            }
+            println!("\nNodes searched: {}", total);
+            Ok(())
+        },
+        Command::Search(board, limits) => {
+            //the NDJSON stream ends with its own bestmove object
+            let best_move: String = searcher.get_best_move(&board, limits);
+            if searcher.info_format() != InfoFormat::Json {
+                println!("bestmove {}", best_move);
+            }
+            Ok(())
+        },
+        Command::Eval(board) => {
+            println!("{}", evaluation::trace_evaluation(&board));
+            Ok(())
+        },
+        Command::Testsuite(args) => testsuite::run_testsuite(searcher, &args.epd_path, args.limits, &args.report_path),
+        Command::Analyse(args) => analysis::analyse_pgn(searcher, &args.input, &args.output, &args.config),
+        Command::Match(config) => tournament::run_match(config),
+        Command::Tune { json } => {
+            print!("{}", if json { tunables::weather_factory_config() } else { tunables::openbench_config() });
+            Ok(())
+        },
+        Command::Spsa(config) => spsa::run_spsa(config),
+        Command::Datagen(config) => datagen::run_datagen(searcher, &config).map(|positions| {
+            println!("Wrote {} positions to {}", positions, config.output);
+        }),
+    };
+    match result {
+        Ok(()) => EXIT_OK,
+        Err(e) => {
+            eprintln!("error: {}", e);
+            EXIT_FAILURE
+        },
+    }
+}
+
+/// Leaf nodes `depth` plies below `board`.
+pub fn perft(board: &Board, depth: u32) -> u64 {
+    if depth == 0 {
+        return 1;
+    }
+    let mut nodes: u64 = 0;
+    board.generate_moves(|moves: PieceMoves| {
+        if depth == 1 {
+            nodes += moves.len() as u64;
+        } else {
+            for m in moves {
+                let mut child: Board = board.clone();
+                child.play(m);
+                nodes += perft(&child, depth - 1);
+            }
+        }
+        false
+    });
+    nodes
+}
+
+/// `startpos` or the words of a FEN.
+fn parse_board(words: &[&str]) -> Result<Board, String> {
+    match words {
+        [] => Err("missing position".to_string()),
+        ["startpos"] => Ok(Board::default()),
+        fen => Board::from_fen(&fen.join(" "), false).map_err(|_| format!("invalid FEN: {}", fen.join(" "))),
+    }
+}
+
+/// Splits `--flag value` pairs; the dashes are optional and the flags in `switches`
+/// take no value.
+fn flags<'a>(words: &[&'a str], switches: &[&str]) -> Result<Vec<(&'a str, Option<&'a str>)>, String> {
+    let mut pairs: Vec<(&str, Option<&str>)> = Vec::new();
+    let mut i: usize = 0;
+    while i < words.len() {
+        let flag: &str = words[i].trim_start_matches('-');
+        if switches.contains(&flag) {
+            pairs.push((flag, None));
+            i += 1;
+        } else {
+            let value: &str = words.get(i + 1).ok_or_else(|| format!("missing value for --{}", flag))?;
+            pairs.push((flag, Some(value)));
+            i += 2;
+        }
+    }
+    Ok(pairs)
+}
+
+fn number<T: std::str::FromStr>(flag: &str, value: Option<&str>) -> Result<T, String> {
+    let value: &str = value.unwrap_or_default();
+    value.parse().map_err(|_| format!("invalid value {:?} for --{}", value, flag))
+}
+
+/// `testsuite <file.epd> [movetime <ms> | depth <n> | nodes <n>] [report <file>]`
+pub fn parse_testsuite(words: &[&str]) -> Result<TestsuiteArgs, String> {
+    let epd_path: &str = words.get(1).ok_or("testsuite needs an EPD file")?;
+    let mut args: TestsuiteArgs = TestsuiteArgs {
+        epd_path: epd_path.to_string(),
+        limits: SearchLimits::default(),
+        report_path: "testsuite_report.csv".to_string(),
+    };
+    for (flag, value) in flags(&words[2..], &[])? {
+        match flag {
+            "movetime" => args.limits.movetime = Some(number(flag, value)?),
+            "depth" => args.limits.depth = Some(number(flag, value)?),
+            "nodes" => args.limits.nodes = Some(number(flag, value)?),
+            "report" => args.report_path = value.unwrap_or_default().to_string(),
+            _ => return Err(format!("unknown testsuite flag --{}", flag)),
+        }
+    }
+    if args.limits.movetime.is_none() && args.limits.depth.is_none() && args.limits.nodes.is_none() {
+        args.limits.movetime = Some(1000);
+    }
+    Ok(args)
+}
+
+/// `analyse <in.pgn> <out.pgn> [--movetime <ms> | --depth <n>] [--multipv <n>] [--cp]`
+pub fn parse_analyse(words: &[&str]) -> Result<AnalyseArgs, String> {
+    let [_, input, output, rest @ ..] = words else {
+        return Err("analyse needs an input and an output PGN file".to_string());
+    };
+    let mut config: AnalysisConfig = AnalysisConfig {
+        limits: SearchLimits { multipv: 3, ..SearchLimits::default() },
+        centipawn_thresholds: false,
+    };
+    for (flag, value) in flags(rest, &["cp"])? {
+        match flag {
+            "movetime" => config.limits.movetime = Some(number(flag, value)?),
+            "depth" => config.limits.depth = Some(number(flag, value)?),
+            "multipv" => config.limits.multipv = number(flag, value)?,
+            "cp" => config.centipawn_thresholds = true,
+            _ => return Err(format!("unknown analyse flag --{}", flag)),
+        }
+    }
+    if config.limits.movetime.is_none() && config.limits.depth.is_none() {
+        config.limits.movetime = Some(1000);
+    }
+    Ok(AnalyseArgs { input: input.to_string(), output: output.to_string(), config })
+}
+
+/// `match <dev> <baseline> [tc 4+0.04] [book <file>] [concurrency <n>] [rounds <pairs>] [pgn <file>] [elo0 <x>] [elo1 <x>] [alpha <x>] [beta <x>]`
+pub fn parse_match(words: &[&str]) -> Result<MatchConfig, String> {
+    let [_, dev, baseline, rest @ ..] = words else {
+        return Err("match needs a dev and a baseline engine".to_string());
+    };
+    let mut config: MatchConfig = MatchConfig {
+        engines: [dev.to_string(), baseline.to_string()],
+        ..MatchConfig::default()
+    };
+    for (flag, value) in flags(rest, &[])? {
+        match flag {
+            "tc" => config.time_control = value.and_then(TimeControl::parse).ok_or_else(|| format!("invalid time control {:?}", value.unwrap_or_default()))?,
+            "book" => config.openings = value.map(str::to_string),
+            "concurrency" => config.concurrency = number(flag, value)?,
+            "rounds" => config.rounds = number(flag, value)?,
+            "pgn" => config.pgn_out = value.map(str::to_string),
+            "elo0" => config.sprt.elo0 = number(flag, value)?,
+            "elo1" => config.sprt.elo1 = number(flag, value)?,
+            "alpha" => config.sprt.alpha = number(flag, value)?,
+            "beta" => config.sprt.beta = number(flag, value)?,
+            _ => return Err(format!("unknown match flag --{}", flag)),
+        }
+    }
+    Ok(config)
+}
+
+/// `tune <engine> [iterations <n>] [tc <base+inc>] [book <file>] [report <n>]`
+pub fn parse_tune(words: &[&str]) -> Result<MatchConfig, String> {
+    let [_, engine, rest @ ..] = words else {
+        return Err("tune needs an engine".to_string());
+    };
+    let mut config: MatchConfig = MatchConfig {
+        engines: [engine.to_string(), engine.to_string()],
+        rounds: 1000,
+        ..MatchConfig::default()
+    };
+    for (flag, value) in flags(rest, &[])? {
+        match flag {
+            "iterations" => config.rounds = number(flag, value)?,
+            "tc" => config.time_control = value.and_then(TimeControl::parse).ok_or_else(|| format!("invalid time control {:?}", value.unwrap_or_default()))?,
+            "book" => config.openings = value.map(str::to_string),
+            "report" => config.rating_interval = number(flag, value)?,
+            _ => return Err(format!("unknown tune flag --{}", flag)),
+        }
+    }
+    Ok(config)
+}
+
+/// `datagen <out> [games <n>] [nodes <n>] [random-plies <n>] [seed <n>]`
+pub fn parse_datagen(words: &[&str]) -> Result<DatagenConfig, String> {
+    let [_, output, rest @ ..] = words else {
+        return Err("datagen needs an output file".to_string());
+    };
+    let mut config: DatagenConfig = DatagenConfig { output: output.to_string(), ..DatagenConfig::default() };
+    for (flag, value) in flags(rest, &[])? {
+        match flag {
+            "games" => config.games = number(flag, value)?,
+            "nodes" => config.nodes = number(flag, value)?,
+            "random-plies" => config.random_plies = number(flag, value)?,
+            "seed" => config.seed = Some(number(flag, value)?),
+            _ => return Err(format!("unknown datagen flag --{}", flag)),
+        }
+    }
+    Ok(config)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(line: &str) -> Vec<String> {
+        line.split_whitespace().map(str::to_string).collect()
+    }
+
+    #[test]
+    fn perft_matches_known_counts() {
+        assert_eq!(perft(&Board::default(), 3), 8902);
+        let kiwipete: Board = "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1".parse().unwrap();
+        assert_eq!(perft(&kiwipete, 2), 2039);
+    }
+
+    #[test]
+    fn subcommands_parse_their_flags() {
+        assert!(matches!(parse(&[]), Ok((Command::Uci, false))));
+        assert!(matches!(parse(&args("--json uci")), Ok((Command::Uci, true))));
+        assert!(matches!(parse(&args("bench --depth 5")), Ok((Command::Bench(5), false))));
+        assert!(matches!(parse(&args("bench 8")), Ok((Command::Bench(8), false))));
+        assert!(matches!(parse(&args("perft startpos 4")), Ok((Command::Perft(_, 4), false))));
+        let Ok((Command::Search(board, limits), true)) = parse(&args("search 8/8/4k3/8/8/4K3/4P3/8 w - - 0 1 --nodes 500 --json")) else {
+            panic!("search did not parse");
+        };
+        assert_eq!((board.side_to_move(), limits.nodes, limits.movetime), (Color::White, Some(500), None));
+        let Ok((Command::Search(_, limits), false)) = parse(&args("search startpos depth 5 multipv 2")) else {
+            panic!("search without dashes did not parse");
+        };
+        assert_eq!((limits.depth, limits.multipv), (Some(5), 2));
+        assert!(matches!(parse(&args("tune --json")), Ok((Command::Tune { json: true }, false))));
+        let Ok((Command::Spsa(config), false)) = parse(&args("tune ./rustbot-tune --iterations 200 tc 2+0.02")) else {
+            panic!("tune did not parse");
+        };
+        assert_eq!((config.engines[1].as_str(), config.rounds, config.time_control.base_ms), ("./rustbot-tune", 200, 2000));
+        let Ok((Command::Datagen(config), false)) = parse(&args("datagen out.txt --games 3 nodes 800")) else {
+            panic!("datagen did not parse");
+        };
+        assert_eq!((config.output.as_str(), config.games, config.nodes), ("out.txt", 3, 800));
+        let config: MatchConfig = parse_match(&["match", "a", "b", "tc", "8+0.08", "--rounds", "10"]).unwrap();
+        assert_eq!((config.time_control.base_ms, config.rounds), (8000, 10));
+        assert!(parse_analyse(&["analyse", "in.pgn", "out.pgn", "--cp", "--depth", "6"]).unwrap().config.centipawn_thresholds);
+    }
+
+    #[test]
+    fn bad_command_lines_are_rejected() {
+        for line in ["bench --depth x", "perft startpos", "perft startpos 0", "search --depth 3", "eval not/a/fen", "testsuite", "match a", "frobnicate", "datagen", "datagen out.txt --games", "tune rustbot --frobs 3", "search startpos --depth"] {
+            assert!(parse(&args(line)).is_err(), "{}", line);
+        }
+    }
+}
@@ -1,8 +1,4 @@
 
-pub const TT_BONUS: i32 = 1 << 24;
-pub const CAPTURE_BONUS: i32 = 1 << 20;
-pub const KILLER_BONUS: i32 = 1 << 20;
-
 pub const MG_PAWN_TABLE: [i32; 64] =      
 [ 0,   0,   0,   0,   0,   0,  0,   0,
 98, 134,  61,  95,  68, 126, 34, -11,
@@ -0,0 +1,157 @@
+use cozy_chess::*;
+use crate::search::*;
+use crate::tournament::insufficient_material;
+use rand::seq::SliceRandom;
+use rand::{Rng, SeedableRng};
+use rand::rngs::StdRng;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+
+pub struct DatagenConfig {
+    pub output: String,
+    pub games: usize,
+    pub nodes: u64, //per move
+    pub random_plies: usize, //random moves played from the start position before the engine takes over
+    pub seed: Option<u64>,
+}
+
+impl Default for DatagenConfig {
+    fn default() -> Self {
+        DatagenConfig { output: String::new(), games: 1000, nodes: 5000, random_plies: 8, seed: None }
+    }
+}
+
+//a game is adjudicated once either side's score passes this for ADJUDICATION_PLIES plies in a row
+const WIN_SCORE: i32 = 2000;
+const ADJUDICATION_PLIES: usize = 4;
+//games still going after this many plies are called drawn
+const MAX_PLIES: usize = 400;
+
+/// A position kept for training, scored from white's point of view.
+struct Sample {
+    fen: String,
+    score: i32,
+}
+
+/// Random legal moves from the start position; `None` if they end the game.
+fn random_opening(rng: &mut StdRng, plies: usize) -> Option<(Board, Vec<u64>)> {
+    let mut board: Board = Board::default();
+    let mut history: Vec<u64> = vec![board.hash()];
+    for _ in 0..plies {
+        let mut moves: Vec<Move> = Vec::new();
+        board.generate_moves(|piece_moves| {
+            moves.extend(piece_moves);
+            false
+        });
+        board.play(*moves.choose(rng)?);
+        history.push(board.hash());
+    }
+    (board.status() == GameStatus::Ongoing).then_some((board, history))
+}
+
+/// Plays one game against itself and returns the kept positions with white's
+/// result: 1.0 for a win, 0.5 for a draw, 0.0 for a loss.
+fn play_game(searcher: &mut AlphaBetaSearcher, rng: &mut StdRng, config: &DatagenConfig) -> (Vec<Sample>, f32) {
+    let (mut board, mut history) = loop {
+        if let Some(opening) = random_opening(rng, config.random_plies) {
+            break opening;
+        }
+    };
+    searcher.new_game();
+    let mut samples: Vec<Sample> = Vec::new();
+    let mut winning_plies: [usize; 2] = [0; 2]; //by color
+    let limits: SearchLimits = SearchLimits { nodes: Some(config.nodes), ..SearchLimits::default() };
+    let result: f32 = loop {
+        match board.status() {
+            GameStatus::Won => break if board.side_to_move() == Color::White { 0.0 } else { 1.0 },
+            GameStatus::Drawn => break 0.5,
+            GameStatus::Ongoing => (),
+        }
+        if insufficient_material(&board) || history.len() > MAX_PLIES || history.iter().filter(|&&h| h == board.hash()).count() >= 3 {
+            break 0.5;
+        }
+        searcher.clear_threefold_repetition();
+        for hash in &history {
+            searcher.add_to_threefold_repetition(*hash);
+        }
+        let search: SearchResult = searcher.search(&board, limits);
+        let stm: Color = board.side_to_move();
+        let winning: usize = if search.score > 0 { stm as usize } else { !stm as usize };
+        if search.score.abs() >= WIN_SCORE {
+            winning_plies[winning] += 1;
+            winning_plies[1 - winning] = 0;
+        } else {
+            winning_plies = [0; 2];
+        }
+        if let Some(color) = winning_plies.iter().position(|&plies| plies >= ADJUDICATION_PLIES) {
+            break if color == Color::White as usize { 1.0 } else { 0.0 };
+        }
+        //the labels are meant for a static evaluation, so only quiet positions are kept
+        let m: Move = search.best_move;
+        let quiet: bool = board.color_on(m.to).is_none() && m.promotion.is_none() && board.checkers().is_empty();
+        if quiet && moves_to_mate(search.score).is_none() {
+            let score: i32 = if stm == Color::White { search.score } else { -search.score };
+            samples.push(Sample { fen: board.to_string(), score });
+        }
+        board.play(m);
+        history.push(board.hash());
+    };
+    (samples, result)
+}
+
+/// Generates training data by fixed-node self-play from randomized openings, one
+/// `<fen> | <score> | <result>` line per position, the score in centipawns and the
+/// result as 1.0, 0.5 or 0.0, both from white's point of view. Returns the number
+/// of positions written.
+pub fn run_datagen(searcher: &mut AlphaBetaSearcher, config: &DatagenConfig) -> std::io::Result<usize> {
+    let mut out = BufWriter::new(File::create(&config.output)?);
+    let mut rng: StdRng = match config.seed {
+        Some(seed) => StdRng::seed_from_u64(seed),
+        None => StdRng::seed_from_u64(rand::thread_rng().gen()),
+    };
+    searcher.set_verbose(false);
+    let mut positions: usize = 0;
+    for game in 1..=config.games {
+        let (samples, result) = play_game(searcher, &mut rng, config);
+        for sample in &samples {
+            writeln!(out, "{} | {} | {:.1}", sample.fen, sample.score, result)?;
+        }
+        positions += samples.len();
+        if game % 10 == 0 || game == config.games {
+            println!("{} games, {} positions", game, positions);
+        }
+    }
+    searcher.set_verbose(true);
+    out.flush()?;
+    Ok(positions)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn generated_lines_hold_a_position_a_score_and_the_result() {
+        let output: String = std::env::temp_dir().join(format!("rustbot_datagen_test_{}.txt", std::process::id())).to_string_lossy().into_owned();
+        let mut searcher: AlphaBetaSearcher = AlphaBetaSearcher::new();
+        searcher.set_hash_size(1);
+        let config: DatagenConfig = DatagenConfig { output: output.clone(), games: 1, nodes: 300, random_plies: 8, seed: Some(7) };
+        let positions: usize = run_datagen(&mut searcher, &config).unwrap();
+        let text: String = std::fs::read_to_string(&output).unwrap();
+        std::fs::remove_file(&output).unwrap();
+        assert!(positions > 0);
+        assert_eq!(text.lines().count(), positions);
+        let results: Vec<&str> = text.lines().map(|line| {
+            let [fen, score, result] = line.split(" | ").collect::<Vec<&str>>()[..] else {
+                panic!("malformed line {}", line);
+            };
+            let board: Board = fen.parse().unwrap();
+            assert!(board.checkers().is_empty());
+            assert!(score.parse::<i32>().is_ok());
+            result
+        }).collect();
+        //every line of a game carries the same result
+        assert!(["1.0", "0.5", "0.0"].contains(&results[0]));
+        assert!(results.iter().all(|r| *r == results[0]));
+    }
+}
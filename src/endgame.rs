@@ -0,0 +1,342 @@
+use cozy_chess::*;
+use crate::constants::*;
+use std::sync::OnceLock;
+
+/// Score of an endgame the stronger side wins by force: above any normal eval, far below mate scores.
+pub const KNOWN_WIN: i32 = 10000;
+/// Scale factors are in 1/SCALE_NORMAL of the eval.
+const SCALE_NORMAL: i32 = 64;
+
+/// Piece counts per color, the key that decides which endgame rules apply.
+struct Material {
+    counts: [[u32; Piece::NUM]; Color::NUM],
+}
+
+impl Material {
+    fn new(board: &Board) -> Self {
+        let mut counts: [[u32; Piece::NUM]; Color::NUM] = [[0; Piece::NUM]; Color::NUM];
+        for color in Color::ALL {
+            for piece in Piece::ALL {
+                counts[color as usize][piece as usize] = board.colored_pieces(color, piece).len();
+            }
+        }
+        Material { counts }
+    }
+
+    fn count(&self, color: Color, piece: Piece) -> u32 {
+        self.counts[color as usize][piece as usize]
+    }
+
+    /// Middlegame material value of `color`'s knights, bishops, rooks and queens.
+    fn non_pawn(&self, color: Color) -> i32 {
+        self.count(color, Piece::Knight) as i32 * MG_KNIGHT_MATERIAL
+            + self.count(color, Piece::Bishop) as i32 * MG_BISHOP_MATERIAL
+            + self.count(color, Piece::Rook) as i32 * MG_ROOK_MATERIAL
+            + self.count(color, Piece::Queen) as i32 * MG_QUEEN_MATERIAL
+    }
+
+    fn minors(&self, color: Color) -> u32 {
+        self.count(color, Piece::Knight) + self.count(color, Piece::Bishop)
+    }
+
+    fn majors(&self, color: Color) -> u32 {
+        self.count(color, Piece::Rook) + self.count(color, Piece::Queen)
+    }
+
+    fn bare_king(&self, color: Color) -> bool {
+        self.count(color, Piece::Pawn) == 0 && self.minors(color) == 0 && self.majors(color) == 0
+    }
+
+    /// Exactly the given pieces besides the king.
+    fn is(&self, color: Color, pawns: u32, knights: u32, bishops: u32) -> bool {
+        self.count(color, Piece::Pawn) == pawns && self.count(color, Piece::Knight) == knights
+            && self.count(color, Piece::Bishop) == bishops && self.majors(color) == 0
+    }
+}
+
+fn distance(a: Square, b: Square) -> i32 {
+    (a.file() as i32 - b.file() as i32).abs().max((a.rank() as i32 - b.rank() as i32).abs())
+}
+
+fn manhattan_distance(a: Square, b: Square) -> i32 {
+    (a.file() as i32 - b.file() as i32).abs() + (a.rank() as i32 - b.rank() as i32).abs()
+}
+
+/// Bonus for a king close to the edge, largest in the corners.
+fn push_to_edge(square: Square) -> i32 {
+    let file: i32 = square.file() as i32;
+    let rank: i32 = square.rank() as i32;
+    20 * ((3 - file).max(file - 4) + (3 - rank).max(rank - 4))
+}
+
+/// Bonus for the attacking king approaching the defending one.
+fn push_close(a: Square, b: Square) -> i32 {
+    10 * (7 - distance(a, b))
+}
+
+fn is_dark(square: Square) -> bool {
+    BitBoard::DARK_SQUARES.has(square)
+}
+
+fn king(board: &Board, color: Color) -> Square {
+    board.colored_pieces(color, Piece::King).next_square().unwrap()
+}
+
+/// Blends `mg` and `eg`, White's PeSTO middlegame and endgame scores of `board`, by
+/// `mg_phase` out of 24. In endgames the piece square tables misjudge the score is
+/// replaced, or its endgame part scaled. Returns the score from White's point of view.
+pub fn adjust_eval(board: &Board, mg: i32, eg: i32, mg_phase: i32) -> i32 {
+    let material: Material = Material::new(board);
+    if insufficient_material(&material) {
+        return 0;
+    }
+    for strong in Color::ALL {
+        if !material.bare_king(!strong) {
+            continue;
+        }
+        if let Some(strong_score) = lone_king_endgame(board, &material, strong) {
+            return if strong == Color::White { strong_score } else { -strong_score };
+        }
+    }
+    let eg: i32 = eg * scale_factor(board, &material, eg) / SCALE_NORMAL;
+    (mg * mg_phase + eg * (24 - mg_phase)) / 24
+}
+
+/// Neither side can mate: no pawns or major pieces, and a lone minor each or two knights against a bare king.
+fn insufficient_material(material: &Material) -> bool {
+    if Color::ALL.into_iter().any(|c| material.count(c, Piece::Pawn) > 0 || material.majors(c) > 0) {
+        return false;
+    }
+    Color::ALL.into_iter().all(|c| material.minors(c) <= 1)
+        || Color::ALL.into_iter().any(|c| material.is(c, 0, 2, 0) && material.bare_king(!c))
+}
+
+/// Score for `strong` when the other side has only its king, or `None` to keep the normal eval.
+fn lone_king_endgame(board: &Board, material: &Material, strong: Color) -> Option<i32> {
+    let strong_king: Square = king(board, strong);
+    let weak_king: Square = king(board, !strong);
+    if material.is(strong, 1, 0, 0) {
+        let pawn: Square = board.colored_pieces(strong, Piece::Pawn).next_square().unwrap();
+        let wins: bool = kpk_wins(strong, strong_king, pawn, weak_king, board.side_to_move() == strong);
+        return Some(if wins { KNOWN_WIN + EG_PAWN_MATERIAL + 10 * pawn.relative_to(strong).rank() as i32 } else { 0 });
+    }
+    let bishops: BitBoard = board.colored_pieces(strong, Piece::Bishop);
+    if material.is(strong, 0, 1, 1) {
+        //mate is only possible in a corner of the bishop's color
+        let corners: [Square; 2] = if is_dark(bishops.next_square().unwrap()) { [Square::A1, Square::H8] } else { [Square::A8, Square::H1] };
+        let corner_distance: i32 = corners.iter().map(|&c| manhattan_distance(weak_king, c)).min().unwrap();
+        return Some(KNOWN_WIN + 20 * (14 - corner_distance) + push_close(strong_king, weak_king));
+    }
+    let pawns: BitBoard = board.colored_pieces(strong, Piece::Pawn);
+    if material.is(strong, material.count(strong, Piece::Pawn), 0, 1) {
+        //the bishop cannot drive the king out of the corner of a rook pawn's promotion square of the other color
+        for file in [File::A, File::H] {
+            let promotion: Square = Square::new(file, Rank::Eighth).relative_to(strong);
+            if (pawns & !file.bitboard()).is_empty() && is_dark(bishops.next_square().unwrap()) != is_dark(promotion)
+                && distance(weak_king, promotion) <= 1
+            {
+                return Some(0);
+            }
+        }
+    }
+    let bishop_pair: bool = !(bishops & BitBoard::DARK_SQUARES).is_empty() && !(bishops & BitBoard::LIGHT_SQUARES).is_empty();
+    if material.majors(strong) > 0 || bishop_pair || (material.count(strong, Piece::Bishop) > 0 && material.count(strong, Piece::Knight) > 0) {
+        let material_eg: i32 = material.count(strong, Piece::Pawn) as i32 * EG_PAWN_MATERIAL
+            + material.count(strong, Piece::Knight) as i32 * EG_KNIGHT_MATERIAL
+            + material.count(strong, Piece::Bishop) as i32 * EG_BISHOP_MATERIAL
+            + material.count(strong, Piece::Rook) as i32 * EG_ROOK_MATERIAL
+            + material.count(strong, Piece::Queen) as i32 * EG_QUEEN_MATERIAL;
+        return Some(KNOWN_WIN + material_eg + push_to_edge(weak_king) + push_close(strong_king, weak_king));
+    }
+    None
+}
+
+/// How much of `eg`, White's endgame score, the side it favors can expect to convert,
+/// in 1/SCALE_NORMAL.
+fn scale_factor(board: &Board, material: &Material, eg: i32) -> i32 {
+    let strong: Color = if eg > 0 { Color::White } else { Color::Black };
+    //without pawns, an extra minor piece or less rarely wins
+    if material.count(strong, Piece::Pawn) == 0 && material.non_pawn(strong) - material.non_pawn(!strong) <= MG_BISHOP_MATERIAL {
+        return if material.non_pawn(strong) < MG_ROOK_MATERIAL {
+            0
+        } else if material.non_pawn(!strong) <= MG_BISHOP_MATERIAL {
+            4
+        } else {
+            14
+        };
+    }
+    //opposite colored bishops and nothing else: extra pawns are often blockaded
+    if Color::ALL.into_iter().all(|c| material.count(c, Piece::Bishop) == 1 && material.count(c, Piece::Knight) == 0 && material.majors(c) == 0) {
+        let white_bishop: Square = board.colored_pieces(Color::White, Piece::Bishop).next_square().unwrap();
+        let black_bishop: Square = board.colored_pieces(Color::Black, Piece::Bishop).next_square().unwrap();
+        if is_dark(white_bishop) != is_dark(black_bishop) {
+            return SCALE_NORMAL / 2;
+        }
+    }
+    SCALE_NORMAL
+}
+
+//KPK bitbase: whether White wins with king and pawn against king, for the pawn on
+//files a-d (the rest is mirrored) and either side to move. Built by retrograde
+//iteration the first time it is needed.
+const KPK_SIZE: usize = 2 * 64 * 64 * 32;
+const INVALID: u8 = 0;
+const UNKNOWN: u8 = 1;
+const DRAW: u8 = 2;
+const WIN: u8 = 4;
+
+static KPK: OnceLock<Vec<bool>> = OnceLock::new();
+
+fn kpk_index(white_to_move: bool, white_king: Square, black_king: Square, pawn: Square) -> usize {
+    (((white_to_move as usize * 64 + white_king as usize) * 64 + black_king as usize) * 8 + pawn.rank() as usize) * 4 + pawn.file() as usize
+}
+
+/// Every pawn square of the bitbase: files a-d, ranks 2-7.
+fn kpk_pawn_squares() -> impl Iterator<Item = Square> {
+    (Rank::Second as usize..=Rank::Seventh as usize)
+        .flat_map(|rank| (0..4).map(move |file| Square::new(File::index(file), Rank::index(rank))))
+}
+
+fn kpk_initial(white_to_move: bool, white_king: Square, black_king: Square, pawn: Square) -> u8 {
+    let pawn_attacks: BitBoard = get_pawn_attacks(pawn, Color::White);
+    if distance(white_king, black_king) <= 1 || white_king == pawn || black_king == pawn || (white_to_move && pawn_attacks.has(black_king)) {
+        return INVALID;
+    }
+    let push: Square = pawn.offset(0, 1);
+    if white_to_move && pawn.rank() == Rank::Seventh && white_king != push
+        && (distance(black_king, push) > 1 || distance(white_king, push) == 1)
+    {
+        return WIN; //promotes and the queen cannot be taken
+    }
+    let black_moves: BitBoard = get_king_moves(black_king) & !(get_king_moves(white_king) | pawn_attacks);
+    if !white_to_move && (black_moves.is_empty() || black_moves.has(pawn)) {
+        return DRAW; //stalemate, or the pawn is taken
+    }
+    UNKNOWN
+}
+
+fn kpk_classify(results: &[u8], white_to_move: bool, white_king: Square, black_king: Square, pawn: Square) -> u8 {
+    //successors of impossible positions index INVALID entries and add nothing
+    let mut reachable: u8 = 0;
+    if white_to_move {
+        for to in get_king_moves(white_king) {
+            reachable |= results[kpk_index(false, to, black_king, pawn)];
+        }
+        if pawn.rank() < Rank::Seventh {
+            let push: Square = pawn.offset(0, 1);
+            reachable |= results[kpk_index(false, white_king, black_king, push)];
+            if pawn.rank() == Rank::Second && push != white_king && push != black_king {
+                reachable |= results[kpk_index(false, white_king, black_king, push.offset(0, 1))];
+            }
+        }
+    } else {
+        for to in get_king_moves(black_king) {
+            reachable |= results[kpk_index(true, white_king, to, pawn)];
+        }
+    }
+    let (good, bad) = if white_to_move { (WIN, DRAW) } else { (DRAW, WIN) };
+    if reachable & good != 0 {
+        good
+    } else if reachable & UNKNOWN != 0 {
+        UNKNOWN
+    } else {
+        bad
+    }
+}
+
+fn generate_kpk() -> Vec<bool> {
+    let mut results: Vec<u8> = vec![INVALID; KPK_SIZE];
+    let positions = || {
+        [false, true].into_iter().flat_map(|white_to_move| {
+            Square::ALL.into_iter().flat_map(move |white_king| {
+                Square::ALL.into_iter().flat_map(move |black_king| {
+                    kpk_pawn_squares().map(move |pawn| (white_to_move, white_king, black_king, pawn))
+                })
+            })
+        })
+    };
+    for (white_to_move, white_king, black_king, pawn) in positions() {
+        results[kpk_index(white_to_move, white_king, black_king, pawn)] = kpk_initial(white_to_move, white_king, black_king, pawn);
+    }
+    let mut changed: bool = true;
+    while changed {
+        changed = false;
+        for (white_to_move, white_king, black_king, pawn) in positions() {
+            let index: usize = kpk_index(white_to_move, white_king, black_king, pawn);
+            if results[index] == UNKNOWN {
+                results[index] = kpk_classify(&results, white_to_move, white_king, black_king, pawn);
+                changed |= results[index] != UNKNOWN;
+            }
+        }
+    }
+    //whatever is still unknown cannot be forced either way, so it is a draw
+    results.into_iter().map(|r| r == WIN).collect()
+}
+
+/// Builds the KPK bitbase now rather than during the first search that needs it.
+pub fn init_bitbases() {
+    KPK.get_or_init(generate_kpk);
+}
+
+/// Whether `strong` wins king and pawn against king.
+fn kpk_wins(strong: Color, strong_king: Square, pawn: Square, weak_king: Square, strong_to_move: bool) -> bool {
+    //seen from White, with the pawn on files a-d
+    let normalize = |square: Square| {
+        let square: Square = square.relative_to(strong);
+        if pawn.file() >= File::E { square.flip_file() } else { square }
+    };
+    KPK.get_or_init(generate_kpk)[kpk_index(strong_to_move, normalize(strong_king), normalize(weak_king), normalize(pawn))]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::evaluation::pesto_evaluate_from_scratch;
+
+    fn eval(fen: &str) -> i32 {
+        pesto_evaluate_from_scratch(&Board::from_fen(fen, false).unwrap())
+    }
+
+    #[test]
+    fn kpk_bitbase_knows_basic_positions() {
+        //king in front of its pawn on the 6th wins whoever moves
+        assert!(eval("4k3/8/4K3/4P3/8/8/8/8 b - - 0 1") < -KNOWN_WIN);
+        assert!(eval("4k3/8/4K3/4P3/8/8/8/8 w - - 0 1") > KNOWN_WIN);
+        //the defending king in front of the pawn holds
+        assert_eq!(eval("8/8/8/8/8/4k3/4P3/4K3 w - - 0 1"), 0);
+        //rook pawn with the defender in the corner
+        assert_eq!(eval("k7/8/K7/P7/8/8/8/8 w - - 0 1"), 0);
+        //outside the square of the pawn, for both colors
+        assert!(eval("7k/8/8/8/P7/8/8/K7 w - - 0 1") > KNOWN_WIN);
+        assert!(eval("k7/8/8/8/7p/8/8/K7 b - - 0 1") > KNOWN_WIN);
+    }
+
+    #[test]
+    fn recognizes_draws_and_wins() {
+        assert_eq!(eval("4k3/8/8/8/8/8/8/2N1K3 w - - 0 1"), 0);
+        assert_eq!(eval("4k3/8/8/8/8/8/8/1NN1K3 w - - 0 1"), 0);
+        //light-squared bishop cannot help the h-pawn queen on dark h8
+        assert_eq!(eval("7k/8/8/7P/8/8/8/3BK3 w - - 0 1"), 0);
+        assert!(eval("7k/8/8/7P/8/8/8/2B1K3 w - - 0 1") > 0);
+        assert!(eval("8/8/8/3k4/8/8/8/R3K3 w - - 0 1") > KNOWN_WIN);
+    }
+
+    #[test]
+    fn kbnk_drives_the_king_to_the_bishops_corner() {
+        //dark-squared bishop: a1 and h8 are the mating corners
+        let right_corner: i32 = eval("8/8/8/8/8/2K5/8/k2NB3 w - - 0 1");
+        let wrong_corner: i32 = eval("k7/8/2K5/8/8/8/8/3NB3 w - - 0 1");
+        assert!(right_corner > wrong_corner && wrong_corner > KNOWN_WIN);
+    }
+
+    #[test]
+    fn opposite_bishops_and_pawnless_endings_are_scaled() {
+        let opposite: i32 = eval("4k3/3b4/8/8/3PP3/8/8/2B1K3 w - - 0 1");
+        let same: i32 = eval("4k3/4b3/8/8/3PP3/8/8/2B1K3 w - - 0 1");
+        assert!(opposite > 0 && opposite < same);
+        //rook against bishop is hard to win
+        assert!(eval("4k3/4b3/8/8/8/8/8/R3K3 w - - 0 1") < 50);
+        //only the endgame part is scaled, so a pawnless middlegame keeps its extra bishop
+        assert!(eval("rn1qkbnr/8/8/8/8/8/8/RNBQKBNR w - - 0 1") > 250);
+    }
+}
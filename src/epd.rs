@@ -0,0 +1,125 @@
+use cozy_chess::*;
+use crate::notation::parse_san;
+
+/// One position of an EPD test suite with the operations we understand.
+pub struct EpdEntry {
+    pub board: Board,
+    pub id: String,
+    pub best_moves: Vec<Move>, //bm
+    pub avoid_moves: Vec<Move>, //am
+    pub comment: String, //c0
+}
+
+impl EpdEntry {
+    pub fn is_solution(&self, m: Move) -> bool {
+        (self.best_moves.is_empty() || self.best_moves.contains(&m)) && !self.avoid_moves.contains(&m)
+    }
+}
+
+pub fn parse_epd_line(line: &str) -> Result<EpdEntry, String> {
+    //the first four fields are a FEN without the move counters, operations follow
+    let mut fields = line.trim().splitn(5, ' ');
+    let mut fen_fields: Vec<&str> = Vec::new();
+    for _ in 0..4 {
+        match fields.next() {
+            Some(field) => fen_fields.push(field),
+            None => return Err(format!("too few fields in EPD line: {}", line)),
+        }
+    }
+    let operations: Vec<(String, Vec<String>)> = split_operations(fields.next().unwrap_or(""));
+    let operand = |opcode: &str| -> Option<&Vec<String>> {
+        operations.iter().find(|(op, _)| op == opcode).map(|(_, operands)| operands)
+    };
+    let halfmove: &str = operand("hmvc").and_then(|o| o.first()).map_or("0", |s| s.as_str());
+    let fullmove: &str = operand("fmvn").and_then(|o| o.first()).map_or("1", |s| s.as_str());
+    let fen: String = format!("{} {} {}", fen_fields.join(" "), halfmove, fullmove);
+    let board: Board = Board::from_fen(&fen, false).map_err(|e| format!("invalid FEN {}: {:?}", fen, e))?;
+
+    let parse_moves = |opcode: &str| -> Result<Vec<Move>, String> {
+        let mut moves: Vec<Move> = Vec::new();
+        for san in operand(opcode).into_iter().flatten() {
+            moves.push(parse_san(&board, san).map_err(|_| format!("invalid {} move {} in {}", opcode, san, fen))?);
+        }
+        Ok(moves)
+    };
+    let best_moves: Vec<Move> = parse_moves("bm")?;
+    let avoid_moves: Vec<Move> = parse_moves("am")?;
+    let id: String = operand("id").map_or(String::new(), |o| o.join(" "));
+    let comment: String = operand("c0").map_or(String::new(), |o| o.join(" "));
+    Ok(EpdEntry { board, id, best_moves, avoid_moves, comment })
+}
+
+/// Parses a test-suite position, which must say what to play (`bm`) or avoid (`am`).
+pub fn parse_epd_test(line: &str) -> Result<EpdEntry, String> {
+    let entry: EpdEntry = parse_epd_line(line)?;
+    if entry.best_moves.is_empty() && entry.avoid_moves.is_empty() {
+        return Err(format!("no bm or am operation in EPD line: {}", line.trim()));
+    }
+    Ok(entry)
+}
+
+fn split_operations(text: &str) -> Vec<(String, Vec<String>)> {
+    //operations are `opcode operand...;`, operands may be quoted strings containing spaces or semicolons
+    let mut operations: Vec<(String, Vec<String>)> = Vec::new();
+    let mut tokens: Vec<String> = Vec::new();
+    let mut token: String = String::new();
+    let mut in_quotes: bool = false;
+    for c in text.chars() {
+        match c {
+            '"' => {
+                if in_quotes {
+                    tokens.push(std::mem::take(&mut token));
+                }
+                in_quotes = !in_quotes;
+            },
+            ';' | ' ' | '\t' if !in_quotes => {
+                if !token.is_empty() {
+                    tokens.push(std::mem::take(&mut token));
+                }
+                if c == ';' && !tokens.is_empty() {
+                    let opcode: String = tokens.remove(0);
+                    operations.push((opcode, std::mem::take(&mut tokens)));
+                }
+            },
+            _ => token.push(c),
+        }
+    }
+    if !token.is_empty() {
+        tokens.push(token);
+    }
+    if !tokens.is_empty() {
+        let opcode: String = tokens.remove(0);
+        operations.push((opcode, tokens));
+    }
+    operations
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_wac_style_line() {
+        let entry = parse_epd_line("2rr3k/pp3pp1/1nnqbN1p/3pN3/2pP4/2P3Q1/PPB4P/R4RK1 w - - bm Qg6; id \"WAC.001\"; c0 \"mate; in three\";").unwrap();
+        assert_eq!(entry.id, "WAC.001");
+        assert_eq!(entry.comment, "mate; in three");
+        assert_eq!(entry.best_moves, vec!["g3g6".parse::<Move>().unwrap()]);
+        assert!(entry.is_solution("g3g6".parse().unwrap()));
+        assert!(!entry.is_solution("f6e8".parse().unwrap()));
+    }
+
+    #[test]
+    fn avoid_moves_are_not_solutions() {
+        let entry = parse_epd_line("r1bqkb1r/pppp1ppp/2n2n2/4p2Q/2B1P3/8/PPPP1PPP/RNB1K1NR w KQkq - am Qxf7+; id \"am test\";").unwrap();
+        assert!(!entry.is_solution("h5f7".parse().unwrap()));
+        assert!(entry.is_solution("d2d3".parse().unwrap()));
+    }
+
+    #[test]
+    fn test_positions_need_bm_or_am() {
+        let line: &str = "4k3/8/8/8/8/8/8/4K2R w K - id \"no solution\";";
+        assert!(parse_epd_line(line).is_ok());
+        assert!(parse_epd_test(line).is_err());
+        assert!(parse_epd_test("4k3/8/8/8/8/8/8/4K2R w K - bm O-O;").is_ok());
+    }
+}
@@ -0,0 +1,20 @@
+use std::fmt;
+
+/// Errors from parsing front-end input. None of them are fatal: the command
+/// is rejected with a diagnostic and the engine keeps its previous state.
+#[derive(Debug)]
+pub enum EngineError {
+    InvalidFen(String),
+    IllegalMove(String),
+    InvalidCommand(String),
+}
+
+impl fmt::Display for EngineError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EngineError::InvalidFen(fen) => write!(f, "invalid FEN: {}", fen),
+            EngineError::IllegalMove(m) => write!(f, "illegal move: {}", m),
+            EngineError::InvalidCommand(message) => write!(f, "invalid command: {}", message),
+        }
+    }
+}
@@ -1,104 +1,182 @@
 use cozy_chess::*;
 use crate::constants::*;
-pub fn pesto_evaluate_from_scratch(board: &Board) -> i32 {
-    let mut white_mg:i32 = 0;
-    let mut black_mg:i32 = 0;
-    let mut white_eg:i32 = 0;
-    let mut black_eg:i32 = 0;
-    let mut mg_phase: i32 = 0;
-    let mut score: i32;
-    let white: BitBoard = board.colors(Color::White);
-    let black: BitBoard = board.colors(Color::Black);
-    //bishop pair bonus
-    let bishops: BitBoard = board.pieces(Piece::Bishop);
-    if (bishops & white).len() > 1 {
-        white_mg += BISHOP_PAIR_MG;
-        white_eg += BISHOP_PAIR_EG;
-    }
-    if (bishops & black).len() > 1 {
-        black_mg += BISHOP_PAIR_MG;
-        black_eg += BISHOP_PAIR_EG;
-    }
-    for square in white.iter() {
-        let piece: Piece = board.piece_on(square).unwrap(); 
-        white_mg += get_square_score_mg(square, Color::White, piece);
-        white_eg += get_square_score_eg(square, Color::White, piece);               
-        //control mg vs eg phase
-        mg_phase += piece_phase(piece);
-        if piece == Piece::Rook {
-            if has_open_file(board, square, Color::White) {
-                white_mg += ROOK_OPEN_FILE_MG;
-                white_eg += ROOK_OPEN_FILE_EG;
-            }
-            else if has_semi_open_file(board, square, Color::White) {
-                white_mg += ROOK_SEMI_OPEN_FILE_MG;
-                white_eg += ROOK_SEMI_OPEN_FILE_EG;
+use crate::endgame;
+use std::fmt;
+
+/// One evaluation term, midgame and endgame values per color.
+#[derive(Clone, Copy, Default, PartialEq, Debug)]
+pub struct Term {
+    pub mg: [i32; Color::NUM],
+    pub eg: [i32; Color::NUM],
+}
+
+impl Term {
+    fn add(&mut self, color: Color, mg: i32, eg: i32) {
+        self.mg[color as usize] += mg;
+        self.eg[color as usize] += eg;
+    }
+}
+
+/// Every term of `pesto_evaluate_from_scratch` for one position. The blended and
+/// final scores are from White's point of view.
+#[derive(Clone, Copy, Default, Debug)]
+pub struct EvalTrace {
+    pub material: Term,
+    pub psqt: Term,
+    pub bishop_pair: Term,
+    pub rook_open_file: Term,
+    pub rook_semi_open_file: Term,
+    pub doubled_pawns: Term,
+    pub pawn_defends_friend: Term,
+    pub passed_pawns: Term,
+    pub tempo: Term,
+    pub mg_phase: i32, //out of 24, the weight of the midgame values
+    pub blended: i32,
+    pub score: i32, //after endgame knowledge
+}
+
+impl EvalTrace {
+    fn terms(&self) -> [(&'static str, Term); 9] {
+        [
+            ("Material", self.material),
+            ("PST", self.psqt),
+            ("Bishop pair", self.bishop_pair),
+            ("Rook open file", self.rook_open_file),
+            ("Rook semi-open file", self.rook_semi_open_file),
+            ("Doubled pawns", self.doubled_pawns),
+            ("Pawn defends friend", self.pawn_defends_friend),
+            ("Passed pawns", self.passed_pawns),
+            ("Tempo", self.tempo),
+        ]
+    }
+
+    /// Sum of every term.
+    pub fn total(&self) -> Term {
+        let mut total: Term = Term::default();
+        for (_, term) in self.terms() {
+            for color in Color::ALL {
+                total.add(color, term.mg[color as usize], term.eg[color as usize]);
             }
         }
-        else if piece == Piece::Pawn {
-            if pawn_is_doubled(board, square, Color::White) {
-                white_mg += DOUBLED_PAWNS_MG;
-                white_eg += DOUBLED_PAWNS_EG;
-            }
-            if pawn_defends_friend(board, square, Color::White) {
-                white_mg += PAWN_DEFENDS_FRIEND_MG;
-                white_eg += PAWN_DEFENDS_FRIEND_EG;
-            
-            }
-            if pawn_is_passed(board, square, Color::White) {
-                white_mg += PASSED_PAWN_TABLE_MG[square.relative_to(Color::Black) as usize];
-                white_eg += PASSED_PAWN_TABLE_EG[square.relative_to(Color::Black) as usize];
-            }
+        total
+    }
+}
+
+impl fmt::Display for EvalTrace {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{:<20}|{:^15}|{:^15}|{:^15}", "Term", "White", "Black", "Total")?;
+        writeln!(f, "{:<20}|{:>7}{:>7} |{:>7}{:>7} |{:>7}{:>7}", "", "mg", "eg", "mg", "eg", "mg", "eg")?;
+        let mut row = |name: &str, term: Term| {
+            let [white_mg, black_mg] = term.mg;
+            let [white_eg, black_eg] = term.eg;
+            writeln!(f, "{:<20}|{:>7}{:>7} |{:>7}{:>7} |{:>7}{:>7}", name, white_mg, white_eg, black_mg, black_eg, white_mg - black_mg, white_eg - black_eg)
+        };
+        for (name, term) in self.terms() {
+            row(name, term)?;
         }
+        row("Sum", self.total())?;
+        writeln!(f, "Phase: {}/24 midgame", self.mg_phase)?;
+        writeln!(f, "Blended: {} cp (White)", self.blended)?;
+        write!(f, "Final: {} cp (White), after endgame knowledge", self.score)
     }
-    for square in black.iter() {
-        let piece: Piece = board.piece_on(square).unwrap();
-        black_mg += get_square_score_mg(square, Color::Black, piece);
-        black_eg += get_square_score_eg(square, Color::Black, piece);
-        //control mg vs eg phase
-        mg_phase += piece_phase(piece);
-        if piece == Piece::Rook {
-            if has_open_file(board, square, Color::Black) {
-                black_mg += ROOK_OPEN_FILE_MG;
-                black_eg += ROOK_OPEN_FILE_EG;
-            }
-            else if has_semi_open_file(board, square, Color::Black) {
-                black_mg += ROOK_SEMI_OPEN_FILE_MG;
-                black_eg += ROOK_SEMI_OPEN_FILE_EG;
+}
+
+/// Score of `board` from the side to move's point of view.
+pub fn pesto_evaluate_from_scratch(board: &Board) -> i32 {
+    let score: i32 = evaluate::<false>(board, &mut EvalTrace::default());
+    if board.side_to_move() == Color::Black {
+        return -score;
+    }
+    score
+}
+
+/// The evaluation of `board` broken down by term.
+pub fn trace_evaluation(board: &Board) -> EvalTrace {
+    let mut trace: EvalTrace = EvalTrace::default();
+    evaluate::<true>(board, &mut trace);
+    trace
+}
+
+/// White's score of `board`. Only fills in `trace` when `TRACE` is set, so the search
+/// pays nothing for it.
+fn evaluate<const TRACE: bool>(board: &Board, trace: &mut EvalTrace) -> i32 {
+    let mut mg: [i32; Color::NUM] = [0; Color::NUM];
+    let mut eg: [i32; Color::NUM] = [0; Color::NUM];
+    let mut mg_phase: i32 = 0;
+    macro_rules! add {
+        ($term:ident, $side:expr, $mg:expr, $eg:expr) => {{
+            let (term_mg, term_eg): (i32, i32) = ($mg, $eg);
+            mg[$side as usize] += term_mg;
+            eg[$side as usize] += term_eg;
+            if TRACE {
+                trace.$term.add($side, term_mg, term_eg);
             }
+        }};
+    }
+    for side in Color::ALL {
+        //bishop pair bonus
+        if board.colored_pieces(side, Piece::Bishop).len() > 1 {
+            add!(bishop_pair, side, BISHOP_PAIR_MG, BISHOP_PAIR_EG);
         }
-        else if piece == Piece::Pawn {
-            if pawn_is_doubled(board, square, Color::Black) {
-                black_mg += DOUBLED_PAWNS_MG;
-                black_eg += DOUBLED_PAWNS_EG;
+        for square in board.colors(side).iter() {
+            let piece: Piece = board.piece_on(square).unwrap();
+            if TRACE {
+                //the tables include material, split it out for the trace only
+                let (material_mg, material_eg) = piece_material(piece);
+                trace.material.add(side, material_mg, material_eg);
+                trace.psqt.add(side, get_square_score_mg(square, side, piece) - material_mg, get_square_score_eg(square, side, piece) - material_eg);
             }
-            if pawn_defends_friend(board, square, Color::Black) {
-                black_mg += PAWN_DEFENDS_FRIEND_MG;
-                black_eg += PAWN_DEFENDS_FRIEND_EG;
+            mg[side as usize] += get_square_score_mg(square, side, piece);
+            eg[side as usize] += get_square_score_eg(square, side, piece);
+            //control mg vs eg phase
+            mg_phase += piece_phase(piece);
+            if piece == Piece::Rook {
+                if has_open_file(board, square, side) {
+                    add!(rook_open_file, side, ROOK_OPEN_FILE_MG, ROOK_OPEN_FILE_EG);
+                }
+                else if has_semi_open_file(board, square, side) {
+                    add!(rook_semi_open_file, side, ROOK_SEMI_OPEN_FILE_MG, ROOK_SEMI_OPEN_FILE_EG);
+                }
             }
-            if pawn_is_passed(board, square, Color::Black) {
-                black_mg += PASSED_PAWN_TABLE_MG[square as usize];
-                black_eg += PASSED_PAWN_TABLE_EG[square as usize];
+            else if piece == Piece::Pawn {
+                if pawn_is_doubled(board, square, side) {
+                    add!(doubled_pawns, side, DOUBLED_PAWNS_MG, DOUBLED_PAWNS_EG);
+                }
+                if pawn_defends_friend(board, square, side) {
+                    add!(pawn_defends_friend, side, PAWN_DEFENDS_FRIEND_MG, PAWN_DEFENDS_FRIEND_EG);
+                }
+                if pawn_is_passed(board, square, side) {
+                    //the tables start at A8 from the pawn's point of view
+                    let rel_square: usize = square.relative_to(!side) as usize;
+                    add!(passed_pawns, side, PASSED_PAWN_TABLE_MG[rel_square], PASSED_PAWN_TABLE_EG[rel_square]);
+                }
             }
         }
     }
-    if board.side_to_move() == Color::White {
-        white_mg += TEMPO_BONUS;
-    }
-    else {
-        black_mg += TEMPO_BONUS;
-    }
-    let mg: i32 = white_mg - black_mg;
-    let eg: i32 = white_eg - black_eg;
-    mg_phase = mg_phase.min(24);
+    add!(tempo, board.side_to_move(), TEMPO_BONUS, 0);
+    let mg_phase: i32 = mg_phase.min(24);
     let eg_phase: i32 = 24 - mg_phase;
-    score = (mg * mg_phase + eg * eg_phase)/ 24;
-    if board.side_to_move() == Color::Black {
-        score = -score;
+    let blended: i32 = ((mg[0] - mg[1]) * mg_phase + (eg[0] - eg[1]) * eg_phase) / 24;
+    let score: i32 = endgame::adjust_eval(board, mg[0] - mg[1], eg[0] - eg[1], mg_phase);
+    if TRACE {
+        trace.mg_phase = mg_phase;
+        trace.blended = blended;
+        trace.score = score;
     }
     score
 }
 
+fn piece_material(piece: Piece) -> (i32, i32) {
+    match piece {
+        Piece::Pawn => (MG_PAWN_MATERIAL, EG_PAWN_MATERIAL),
+        Piece::Knight => (MG_KNIGHT_MATERIAL, EG_KNIGHT_MATERIAL),
+        Piece::Bishop => (MG_BISHOP_MATERIAL, EG_BISHOP_MATERIAL),
+        Piece::Rook => (MG_ROOK_MATERIAL, EG_ROOK_MATERIAL),
+        Piece::Queen => (MG_QUEEN_MATERIAL, EG_QUEEN_MATERIAL),
+        Piece::King => (MG_KING_MATERIAL, EG_KING_MATERIAL),
+    }
+}
+
 pub fn has_open_file(board: &Board, square: Square, side: Color) -> bool {
     //check if the piece on this square has access to an open file in front of it
     //this is used for rooks
@@ -110,7 +188,7 @@ pub fn has_open_file(board: &Board, square: Square, side: Color) -> bool {
     //check that there are no enemy pawns on the file
     let enemy_pawns: BitBoard = board.colored_pieces(other_side, Piece::Pawn);
     let friendly_pawns: BitBoard = board.colored_pieces(side, Piece::Pawn);
-    return (file & (friendly_pawns | enemy_pawns)).is_empty();
+    (file & (friendly_pawns | enemy_pawns)).is_empty()
 }
 
 pub fn has_semi_open_file(board: &Board, square: Square, side: Color) -> bool {
@@ -118,14 +196,14 @@ pub fn has_semi_open_file(board: &Board, square: Square, side: Color) -> bool {
     //this is used for rooks
     let file: BitBoard = square.file().bitboard();
     let friendly_pawns: BitBoard = board.colored_pieces(side, Piece::Pawn);
-    return (file & friendly_pawns).is_empty();
+    (file & friendly_pawns).is_empty()
 }
 
 pub fn pawn_is_doubled(board: &Board, square: Square, side: Color) -> bool {
     //check if the pawn on this square is doubled
     let file: BitBoard = square.file().bitboard();
     let friendly_pawns: BitBoard = board.colored_pieces(side, Piece::Pawn);
-    return (file & friendly_pawns).len() > 1;
+    (file & friendly_pawns).len() > 1
 }
 
 pub fn get_square_score_mg(square: Square, side: Color, piece: Piece) -> i32 {
@@ -177,7 +255,7 @@ pub fn pawn_defends_friend(board: &Board, square: Square, side: Color) -> bool {
     //check if a pawn on the passed square defends a friendly piece
     let friendly_pieces: BitBoard = board.colors(side);
     let pawn_attacks: BitBoard = get_pawn_attacks(square, side);
-    return !(pawn_attacks & friendly_pieces).is_empty();
+    !(pawn_attacks & friendly_pieces).is_empty()
 }
 
 pub fn pawn_is_passed(board: &Board, square: Square, side: Color)-> bool {
@@ -200,5 +278,38 @@ pub fn pawn_is_passed(board: &Board, square: Square, side: Color)-> bool {
     }
 
     let enemy_pawns: BitBoard = board.colored_pieces(other_side, Piece::Pawn);
-    return (file & enemy_pawns).is_empty();
-}
\ No newline at end of file
+    (file & enemy_pawns).is_empty()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn trace_matches_the_evaluation() {
+        for fen in [
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+            "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 b - - 0 1",
+        ] {
+            let board: Board = Board::from_fen(fen, false).unwrap();
+            let trace: EvalTrace = trace_evaluation(&board);
+            let white_score: i32 = if board.side_to_move() == Color::White { trace.score } else { -trace.score };
+            assert_eq!(white_score, pesto_evaluate_from_scratch(&board), "{}", fen);
+            let total: Term = trace.total();
+            let blended: i32 = ((total.mg[0] - total.mg[1]) * trace.mg_phase + (total.eg[0] - total.eg[1]) * (24 - trace.mg_phase)) / 24;
+            assert_eq!(blended, trace.blended, "{}", fen);
+        }
+    }
+
+    #[test]
+    fn trace_reports_individual_terms() {
+        //white has the bishop pair and doubled c-pawns, both rooks are on open files
+        let board: Board = Board::from_fen("4r1k1/p4ppp/8/8/8/2P5/2P2PPP/3RBBK1 w - - 0 1", false).unwrap();
+        let trace: EvalTrace = trace_evaluation(&board);
+        assert_eq!(trace.bishop_pair.mg, [BISHOP_PAIR_MG, 0]);
+        assert_eq!(trace.doubled_pawns.eg, [2 * DOUBLED_PAWNS_EG, 0]);
+        assert_eq!(trace.rook_open_file.mg, [ROOK_OPEN_FILE_MG, ROOK_OPEN_FILE_MG]);
+        assert_eq!(trace.tempo.mg, [TEMPO_BONUS, 0]);
+        assert_eq!(trace.material.mg[1], MG_ROOK_MATERIAL + 4 * MG_PAWN_MATERIAL);
+    }
+}
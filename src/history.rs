@@ -0,0 +1,236 @@
+use cozy_chess::*;
+
+/// History scores saturate towards +-MAX_HISTORY: each update is scaled down by how
+/// close the entry already is to the limit ("gravity"), so old results fade out.
+pub const MAX_HISTORY: i32 = 16384;
+/// Piece and destination of a move, the key of continuation and capture history.
+/// Pieces are indexed by color and type, so 12 values.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct PieceTo {
+    pub piece: usize,
+    pub to: Square,
+}
+
+impl PieceTo {
+    /// `m` must be legal in `board`; read before the move is played.
+    pub fn new(board: &Board, m: Move) -> Self {
+        let piece: Piece = board.piece_on(m.from).unwrap();
+        PieceTo { piece: board.side_to_move() as usize * Piece::NUM + piece as usize, to: m.to }
+    }
+    fn index(&self) -> usize {
+        self.piece * Square::NUM + self.to as usize
+    }
+}
+
+const PIECE_TO_NUM: usize = Color::NUM * Piece::NUM * Square::NUM;
+
+/// Bonus for the move that caused a cutoff, and the malus for moves tried before it.
+pub fn history_bonus(depth: i32) -> i32 {
+    (depth * depth).min(1200)
+}
+
+fn apply_gravity(entry: &mut i16, bonus: i32, limit: i32) {
+    let value: i32 = *entry as i32;
+    *entry = (value + bonus - value * bonus.abs() / limit) as i16;
+}
+
+/// Move ordering statistics gathered during search:
+/// - butterfly history, quiet moves by side and from/to squares
+/// - counter moves, the quiet refutation of the opponent's previous move
+/// - continuation history, quiet moves by the moves 1 and 2 plies earlier
+/// - capture history, captures by moving piece, destination and captured piece
+pub struct HistoryTables {
+    butterfly: Vec<i16>,
+    counter_moves: Vec<Option<Move>>,
+    continuation: [Vec<i16>; 2], //indexed by plies back minus one, then previous then current piece-to
+    capture: Vec<i16>,
+}
+
+impl HistoryTables {
+    pub fn new() -> Self {
+        HistoryTables {
+            butterfly: vec![0; Color::NUM * Square::NUM * Square::NUM],
+            counter_moves: vec![None; PIECE_TO_NUM],
+            continuation: [vec![0; PIECE_TO_NUM * PIECE_TO_NUM], vec![0; PIECE_TO_NUM * PIECE_TO_NUM]],
+            capture: vec![0; PIECE_TO_NUM * Piece::NUM],
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.butterfly.fill(0);
+        self.counter_moves.fill(None);
+        self.continuation[0].fill(0);
+        self.continuation[1].fill(0);
+        self.capture.fill(0);
+    }
+
+    fn butterfly_index(side: Color, m: Move) -> usize {
+        (side as usize * Square::NUM + m.from as usize) * Square::NUM + m.to as usize
+    }
+
+    fn capture_index(piece_to: PieceTo, captured: Piece) -> usize {
+        piece_to.index() * Piece::NUM + captured as usize
+    }
+
+    pub fn counter_move(&self, previous: Option<PieceTo>) -> Option<Move> {
+        self.counter_moves[previous?.index()]
+    }
+
+    /// Ordering score of a quiet move. `previous` holds the moves 1 and 2 plies back,
+    /// `None` at the root or after a null move.
+    pub fn quiet_score(&self, side: Color, m: Move, piece_to: PieceTo, previous: [Option<PieceTo>; 2]) -> i32 {
+        let mut score: i32 = self.butterfly[Self::butterfly_index(side, m)] as i32;
+        for (table, prev) in self.continuation.iter().zip(previous) {
+            if let Some(prev) = prev {
+                score += table[prev.index() * PIECE_TO_NUM + piece_to.index()] as i32;
+            }
+        }
+        score
+    }
+
+    pub fn capture_score(&self, piece_to: PieceTo, captured: Piece) -> i32 {
+        self.capture[Self::capture_index(piece_to, captured)] as i32
+    }
+
+    /// `bonus` is negative for moves that were searched before the cutoff move.
+    pub fn update_quiet(&mut self, side: Color, m: Move, piece_to: PieceTo, previous: [Option<PieceTo>; 2], bonus: i32) {
+        apply_gravity(&mut self.butterfly[Self::butterfly_index(side, m)], bonus, MAX_HISTORY);
+        for (table, prev) in self.continuation.iter_mut().zip(previous) {
+            if let Some(prev) = prev {
+                apply_gravity(&mut table[prev.index() * PIECE_TO_NUM + piece_to.index()], bonus, MAX_HISTORY);
+            }
+        }
+    }
+
+    pub fn set_counter_move(&mut self, previous: Option<PieceTo>, m: Move) {
+        if let Some(previous) = previous {
+            self.counter_moves[previous.index()] = Some(m);
+        }
+    }
+
+    pub fn update_capture(&mut self, piece_to: PieceTo, captured: Piece, bonus: i32) {
+        apply_gravity(&mut self.capture[Self::capture_index(piece_to, captured)], bonus, MAX_HISTORY);
+    }
+}
+
+/// Entries per correction table, indexed by structure hash.
+const CORRECTION_SIZE: usize = 16384;
+/// Corrections are stored in 1/CORRECTION_GRAIN cp.
+const CORRECTION_GRAIN: i32 = 32;
+const MAX_CORRECTION: i32 = 64 * CORRECTION_GRAIN;
+
+fn structure_hash(bitboards: [BitBoard; 2]) -> usize {
+    let h: u64 = bitboards[0].0.wrapping_mul(0x9E3779B97F4A7C15) ^ bitboards[1].0.wrapping_mul(0xC2B2AE3D27D4EB4F);
+    (h >> 32) as usize % CORRECTION_SIZE
+}
+
+/// Learns how far search results land from the static eval in a given pawn
+/// structure, and for each color's non-pawn pieces, with the side to move.
+/// PeSTO misjudges some structures consistently; this moves the eval used for
+/// pruning towards what the search found.
+pub struct CorrectionHistory {
+    pawn: Vec<i16>, //side to move, pawn hash
+    non_pawn: Vec<i16>, //side to move, us or them, that side's non-pawn hash
+}
+
+impl CorrectionHistory {
+    pub fn new() -> Self {
+        CorrectionHistory {
+            pawn: vec![0; Color::NUM * CORRECTION_SIZE],
+            non_pawn: vec![0; Color::NUM * Color::NUM * CORRECTION_SIZE],
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.pawn.fill(0);
+        self.non_pawn.fill(0);
+    }
+
+    /// Structures are hashed as seen by the side to move, so a position and its
+    /// color flipped twin share every index, collisions included.
+    fn indices(board: &Board) -> [usize; 3] {
+        let side: Color = board.side_to_move();
+        let relative = |bitboard: BitBoard| if side == Color::White { bitboard } else { bitboard.flip_ranks() };
+        let pawn_hash: usize = structure_hash([
+            relative(board.colored_pieces(side, Piece::Pawn)),
+            relative(board.colored_pieces(!side, Piece::Pawn)),
+        ]);
+        let non_pawn = |color: Color| {
+            let minors: BitBoard = (board.pieces(Piece::Knight) | board.pieces(Piece::Bishop)) & board.colors(color);
+            let majors: BitBoard = (board.pieces(Piece::Rook) | board.pieces(Piece::Queen) | board.pieces(Piece::King)) & board.colors(color);
+            structure_hash([relative(minors), relative(majors)])
+        };
+        let side: usize = side as usize;
+        [
+            side * CORRECTION_SIZE + pawn_hash,
+            (side * Color::NUM) * CORRECTION_SIZE + non_pawn(board.side_to_move()),
+            (side * Color::NUM + 1) * CORRECTION_SIZE + non_pawn(!board.side_to_move()),
+        ]
+    }
+
+    /// Correction in cp to add to the static eval of `board`.
+    pub fn correction(&self, board: &Board) -> i32 {
+        let [pawn, us, them] = Self::indices(board);
+        let total: i32 = 2 * self.pawn[pawn] as i32 + self.non_pawn[us] as i32 + self.non_pawn[them] as i32;
+        (total / (2 * CORRECTION_GRAIN)).clamp(-MAX_CORRECTION / CORRECTION_GRAIN, MAX_CORRECTION / CORRECTION_GRAIN)
+    }
+
+    /// `error` is the search score minus the corrected static eval, so repeated
+    /// updates settle where the correction matches the search. Deeper searches are
+    /// trusted more.
+    pub fn update(&mut self, board: &Board, error: i32, depth: i32) {
+        let bonus: i32 = (error * CORRECTION_GRAIN * depth.min(16) / 64).clamp(-MAX_CORRECTION / 4, MAX_CORRECTION / 4);
+        let [pawn, us, them] = Self::indices(board);
+        apply_gravity(&mut self.pawn[pawn], bonus, MAX_CORRECTION);
+        apply_gravity(&mut self.non_pawn[us], bonus, MAX_CORRECTION);
+        apply_gravity(&mut self.non_pawn[them], bonus, MAX_CORRECTION);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn gravity_keeps_scores_bounded() {
+        let mut entry: i16 = 0;
+        for _ in 0..1000 {
+            apply_gravity(&mut entry, history_bonus(40), MAX_HISTORY);
+        }
+        assert!(entry as i32 <= MAX_HISTORY && entry > 0);
+        for _ in 0..1000 {
+            apply_gravity(&mut entry, -history_bonus(40), MAX_HISTORY);
+        }
+        assert!(entry as i32 >= -MAX_HISTORY && entry < 0);
+    }
+
+    #[test]
+    fn continuation_history_is_keyed_by_previous_move() {
+        let mut tables: HistoryTables = HistoryTables::new();
+        let board: Board = Board::default();
+        let m: Move = "g1f3".parse().unwrap();
+        let piece_to: PieceTo = PieceTo::new(&board, m);
+        let e5: PieceTo = PieceTo { piece: Color::Black as usize * Piece::NUM + Piece::Pawn as usize, to: Square::E5 };
+        let d5: PieceTo = PieceTo { piece: Color::Black as usize * Piece::NUM + Piece::Pawn as usize, to: Square::D5 };
+        tables.update_quiet(Color::White, m, piece_to, [Some(e5), None], 100);
+        let butterfly_only: i32 = tables.quiet_score(Color::White, m, piece_to, [None, None]);
+        assert_eq!(tables.quiet_score(Color::White, m, piece_to, [Some(e5), None]), butterfly_only + 100);
+        assert_eq!(tables.quiet_score(Color::White, m, piece_to, [Some(d5), None]), butterfly_only);
+    }
+
+    #[test]
+    fn correction_follows_search_and_stays_clamped() {
+        let mut correction: CorrectionHistory = CorrectionHistory::new();
+        let board: Board = Board::default();
+        for _ in 0..50 {
+            let error: i32 = 40 - correction.correction(&board);
+            correction.update(&board, error, 10);
+        }
+        let learned: i32 = correction.correction(&board);
+        assert!((35..=45).contains(&learned), "{}", learned);
+        for _ in 0..1000 {
+            correction.update(&board, 100000, 16);
+        }
+        assert!(correction.correction(&board) <= MAX_CORRECTION / CORRECTION_GRAIN);
+    }
+}
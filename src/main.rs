@@ -1,120 +1,223 @@
 mod search;
+mod history;
+mod see;
+mod movepick;
 mod constants;
 mod evaluation;
-use cozy_chess::*;
-use search::AlphaBetaSearcher;
+mod endgame;
+mod notation;
+mod epd;
+mod testsuite;
+mod sprt;
+mod tournament;
+mod pgn;
+mod analysis;
+mod bench;
+mod error;
+mod uci;
+mod strength;
+mod tunables;
+mod tt;
+mod cli;
+mod ndjson;
+mod xboard;
+mod datagen;
+mod spsa;
+#[cfg(test)]
+mod symmetry;
+#[cfg(feature = "stats")]
+mod stats;
+use search::{AlphaBetaSearcher, InfoFormat};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
 
 fn main() {
-    let mut board: Board = Board::default();
+    let mut position: uci::GamePosition = uci::GamePosition::default();
     let mut input: String = String::new();
     let mut searcher: AlphaBetaSearcher = AlphaBetaSearcher::new();
+    let mut strength_settings: strength::StrengthSettings = strength::StrengthSettings::default();
+    let mut hash_file: String = String::new(); //default file of `savehash`, `loadhash` and the Save/Load Hash buttons
+    //with a subcommand the engine runs it and exits, see `cli::USAGE`
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let (command, json): (cli::Command, bool) = match cli::parse(&args) {
+        Ok(parsed) => parsed,
+        Err(e) => {
+            eprintln!("error: {}\n\n{}", e, cli::USAGE);
+            std::process::exit(cli::EXIT_USAGE);
+        }
+    };
+    if json {
+        searcher.set_info_format(InfoFormat::Json);
+    }
+    if !matches!(command, cli::Command::Uci) {
+        std::process::exit(cli::run(command, &mut searcher));
+    }
     loop {
         input.clear();
         searcher.clear_threefold_repetition();
-        std::io::stdin().read_line(&mut input).unwrap();
+        match std::io::stdin().read_line(&mut input) {
+            Ok(0) => break, //stdin closed, same as quit
+            Ok(_) => (),
+            Err(e) => {
+                println!("info string failed to read input: {}", e);
+                continue;
+            }
+        }
         let input = input.trim();
 
         if input.starts_with("ucinewgame") {
-            board = Board::default();
+            position = uci::GamePosition::default();
+            searcher.new_game();
+        } else if input.starts_with("xboard") {
+            //the rest of the session speaks CECP
+            xboard::run(&mut searcher);
+            break;
         } else if input.starts_with("uci") {
             println!("id name slinky_chess");
             println!("id author Nathan");
+            println!("option name UCI_LimitStrength type check default false");
+            println!("option name UCI_Elo type spin default {} min {} max {}", strength::MAX_ELO, strength::MIN_ELO, strength::MAX_ELO);
+            println!("option name Skill Level type spin default {} min 0 max {}", strength::MAX_SKILL_LEVEL, strength::MAX_SKILL_LEVEL);
+            println!("option name Hash type spin default {} min 1 max {}", tt::DEFAULT_HASH_MB, tt::MAX_HASH_MB);
+            println!("option name Clear Hash type button");
+            println!("option name Hash File type string default <empty>");
+            println!("option name Save Hash type button");
+            println!("option name Load Hash type button");
+            println!("option name JSON Output type check default false");
+            #[cfg(feature = "tune")]
+            for t in tunables::TUNABLES {
+                println!("option name {} type spin default {} min {} max {}", t.name, t.default, t.min, t.max);
+            }
             println!("uciok");
         } else if input.starts_with("isready") {
+            //the table is allocated here rather than at startup, after any Hash option
+            searcher.allocate_hash();
+            endgame::init_bitbases();
             println!("readyok");
-        } else if input.starts_with("position startpos moves") {
-            board = Board::default();
-            let moves = input.split_whitespace().skip(3);
-            for m in moves {
-                match util::parse_uci_move(&board, m) {
-                    Ok(ucimove) => {
-                        board.play(ucimove);
-                        searcher.add_to_threefold_repetition(board.hash());
-                    },
-                    Err(e) => {
-                        eprintln!("Failed to parse move: {}. Error: {:?}", m, e);
-                        break;
-                    }
-                }
-            }
-        } else if input.starts_with("position startpos") {
-            board = Board::default();
-        } else if input.starts_with("position fen") {
-            let parts: Vec<&str> = input.split_whitespace().collect();
-            let fen_end = parts.iter().position(|&x| x == "moves").unwrap_or(parts.len());
-            let fen = parts[2..fen_end].join(" ");
-            
-            match Board::from_fen(&fen, false) {
-                Ok(new_board) => board = new_board,
+        } else if input.starts_with("setoption") {
+            let (name, value) = match uci::parse_setoption(input) {
+                Ok(option) => option,
                 Err(e) => {
-                    eprintln!("Failed to parse FEN: {}. Error: {:?}", fen, e);
+                    println!("info string {}", e);
                     continue;
                 }
+            };
+            match name.to_lowercase().as_str() {
+                "uci_limitstrength" => strength_settings.limit_strength = value == "true",
+                "uci_elo" => strength_settings.elo = value.parse().unwrap_or(strength_settings.elo),
+                "skill level" => strength_settings.skill_level = value.parse().unwrap_or(strength_settings.skill_level),
+                "hash" => match value.parse() {
+                    Ok(size_mb) => searcher.set_hash_size(size_mb),
+                    Err(_) => println!("info string invalid hash size {}", value),
+                },
+                "clear hash" => searcher.clear_hash(),
+                "hash file" => hash_file = if value == "<empty>" { String::new() } else { value },
+                "save hash" => save_hash(&searcher, &hash_file),
+                "load hash" => load_hash(&mut searcher, &hash_file),
+                "json output" => searcher.set_info_format(if value == "true" { InfoFormat::Json } else { InfoFormat::Uci }),
+                #[cfg(feature = "tune")]
+                tunable if value.parse().is_ok_and(|v| tunables::set(tunable, v)) => (),
+                _ => println!("info string unknown option {}", name),
             }
-            
-            if let Some(moves_index) = parts.iter().position(|&x| x == "moves") {
-                for m in parts.iter().skip(moves_index + 1) {
-                    match util::parse_uci_move(&board, m) {
-                        Ok(ucimove) => {
-                            board.play(ucimove);
-                            searcher.add_to_threefold_repetition(board.hash());
-                            
-                        },
-                        Err(e) => {
-                            eprintln!("Failed to parse move: {}. Error: {:?}", m, e);
-                            break;
-                        }
-                    }
-                }
+            searcher.set_strength(strength_settings);
+        } else if input.starts_with("position") {
+            //a malformed command leaves the previous position untouched
+            match uci::parse_position(input) {
+                Ok(new_position) => position = new_position,
+                Err(e) => println!("info string {}", e),
             }
         } else if input.starts_with("go") {
+            let params: uci::GoParams = uci::parse_go(input).unwrap_or_else(|e| {
+                println!("info string {}, searching depth 1 only", e);
+                uci::GoParams { depth: Some(1), ..uci::GoParams::default() }
+            });
+            searcher.clear_threefold_repetition();
+            for hash in &position.history {
+                searcher.add_to_threefold_repetition(*hash);
+            }
+            //an infinite search is ended by `stop`, and its bestmove waits for it even
+            //when the search finishes early, as the protocol asks
+            let stop: Arc<AtomicBool> = searcher.stop_signal();
+            stop.store(false, Ordering::Relaxed);
+            let watcher = params.infinite.then(|| uci::watch_for_stop(Arc::clone(&stop)));
+            let best_move: String = searcher.get_best_move(&position.board, params.limits(position.board.side_to_move()));
+            let quit: bool = watcher.is_some_and(|watcher| watcher.join().unwrap_or(true));
+            println!("bestmove {}", best_move);
+            if quit {
+                break;
+            }
+        } else if input.starts_with("testsuite") {
+            //testsuite <file.epd> [movetime <ms> | depth <n> | nodes <n>] [report <file>]
             let words: Vec<&str> = input.split_whitespace().collect();
-            let mut i: usize = 0;
-            let mut wtime: u64 = 0;
-            let mut btime: u64 = 0;
-            let mut movetime: u64 = 0;
-            while i < words.len() {
-                match words[i] {
-                    "wtime" | "btime" | "winc" | "binc" | "movetime" => {
-                        if i + 1 < words.len() {
-                            if let Ok(value) = words[i + 1].parse::<u64>() {
-                                match words[i] {
-                                    "wtime" => wtime = value,
-                                    "btime" => btime = value,
-                                    "movetime" => movetime = value,
-                                    _ => (),
-                                }
-                            } else {
-                                eprintln!("Error parsing {}: Invalid number", words[i]);
-                            }
-                            i += 2;
-                        } else {
-                            eprintln!("Missing value for {}", words[i]);
-                            i += 1;
-                        }
-                    },
-                    _ => i += 1,
-                }
+            match cli::parse_testsuite(&words) {
+                Ok(args) => if let Err(e) = testsuite::run_testsuite(&mut searcher, &args.epd_path, args.limits, &args.report_path) {
+                    println!("info string failed to run test suite {}: {}", args.epd_path, e);
+                },
+                Err(e) => println!("info string {}", e),
             }
-            //if the input uses wtime/btime, use that, otherwise use movetime
-            let time_remaining = if board.side_to_move() == Color::White {
-                if wtime > 0 {
-                    wtime
-                } else {
-                    movetime
-                }
+        } else if input.starts_with("match") {
+            //match <dev> <baseline> [tc 4+0.04] [book <file.epd>] [concurrency <n>] [rounds <pairs>] [pgn <file>] [elo0 <x>] [elo1 <x>] [alpha <x>] [beta <x>]
+            let words: Vec<&str> = input.split_whitespace().collect();
+            match cli::parse_match(&words) {
+                Ok(config) => if let Err(e) = tournament::run_match(config) {
+                    println!("info string match failed: {}", e);
+                },
+                Err(e) => println!("info string {}", e),
+            }
+        } else if input.starts_with("analyse") {
+            //analyse <in.pgn> <out.pgn> [--movetime <ms> | --depth <n>] [--multipv <n>] [--cp]
+            let words: Vec<&str> = input.split_whitespace().collect();
+            match cli::parse_analyse(&words) {
+                Ok(args) => if let Err(e) = analysis::analyse_pgn(&mut searcher, &args.input, &args.output, &args.config) {
+                    println!("info string failed to analyse {}: {}", args.input, e);
+                },
+                Err(e) => println!("info string {}", e),
+            }
+        } else if input.starts_with("bench") {
+            //bench [depth]
+            let depth: i32 = input.split_whitespace().nth(1).and_then(|d| d.parse().ok()).unwrap_or(7);
+            bench::run_bench(&mut searcher, depth);
+        } else if input.starts_with("eval") {
+            //eval: the static evaluation of the current position, term by term
+            println!("{}", evaluation::trace_evaluation(&position.board));
+        } else if input.starts_with("savehash") || input.starts_with("loadhash") {
+            //savehash [file] / loadhash [file], the Hash File option when no file is given
+            let path: &str = input.split_whitespace().nth(1).unwrap_or(&hash_file);
+            if input.starts_with("savehash") {
+                save_hash(&searcher, path);
             } else {
-                if btime > 0 {
-                    btime
-                } else {
-                    movetime
-                }
-            };
-
-            let best_move: String = searcher.get_best_move(&board, time_remaining);
-            println!("bestmove {}", best_move);
+                load_hash(&mut searcher, path);
+            }
+        } else if input.starts_with("tune") {
+            //tune [json]: the same config as the `tune` subcommand prints
+            let json: bool = input.split_whitespace().nth(1) == Some("json");
+            cli::run(cli::Command::Tune { json }, &mut searcher);
+        } else if input == "d" {
+            print!("{}", uci::display(&position.board));
+        } else if input == "fen" {
+            println!("{}", position.board);
+        } else if input == "moves" {
+            //moves: every legal move, in UCI and SAN
+            print!("{}", uci::list_moves(&position.board));
+        } else if input == "flip" {
+            //flip: the same position with colors swapped, its game history dropped
+            position = uci::GamePosition::new(uci::flip_board(&position.board));
         } else if input.starts_with("quit") {
             break;
         }
     }
-}
\ No newline at end of file
+}
+
+fn save_hash(searcher: &AlphaBetaSearcher, path: &str) {
+    match searcher.save_hash(path) {
+        Ok(entries) => println!("info string saved {} hash entries to {}", entries, path),
+        Err(e) => println!("info string failed to save hash to '{}': {}", path, e),
+    }
+}
+
+fn load_hash(searcher: &mut AlphaBetaSearcher, path: &str) {
+    match searcher.load_hash(path) {
+        Ok(entries) => println!("info string loaded {} hash entries from {}", entries, path),
+        Err(e) => println!("info string failed to load hash from '{}': {}", path, e),
+    }
+}
@@ -0,0 +1,286 @@
+use arrayvec::ArrayVec;
+use cozy_chess::*;
+use crate::history::*;
+use crate::see::see_ge;
+
+/// Most valuable victim - least valuable attacker values, indexed by piece.
+const MVV_LVA: [i32; Piece::NUM] = [1, 3, 3, 5, 9, 0];
+
+/// A pawn moving diagonally is a capture even onto the empty en passant square.
+pub fn is_capture(board: &Board, m: &Move) -> bool {
+    board.color_on(m.to) == Some(!board.side_to_move())
+        || (board.piece_on(m.from) == Some(Piece::Pawn) && m.from.file() != m.to.file())
+}
+
+/// Captures and queen promotions, the moves searched before quiets.
+fn is_noisy(board: &Board, m: &Move) -> bool {
+    is_capture(board, m) || m.promotion == Some(Piece::Queen)
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Stage {
+    TtMove,
+    GenerateNoisy,
+    GoodNoisy,
+    Killer1,
+    Killer2,
+    CounterMove,
+    GenerateQuiets,
+    Quiets,
+    BadNoisy,
+    QsearchGenerate,
+    QsearchNoisy,
+    Done,
+}
+
+#[derive(Clone, Copy)]
+struct ScoredMove {
+    m: Move,
+    key: i64, //score first, then the squares as seen by the mover to break ties
+}
+
+impl ScoredMove {
+    /// Equal scores are ordered by the squares relative to `side`, so a position and
+    /// its color flipped twin order their moves alike whatever order they were
+    /// generated in.
+    fn new(m: Move, score: i32, side: Color) -> Self {
+        let squares: i64 = (m.from.relative_to(side) as i64) << 9 | (m.to.relative_to(side) as i64) << 3;
+        ScoredMove { m, key: (score as i64) << 16 | squares | m.promotion.map_or(0, |p| p as i64 + 1) }
+    }
+}
+
+/// Hands out the moves of a node one at a time, best first, generating and scoring
+/// each group only when the previous one is exhausted:
+/// TT move, captures that win material by SEE, killers, counter move, quiets by
+/// history, then the losing captures. Moves are picked by selection on demand, so
+/// a node that cuts off early never sorts the rest.
+pub struct MovePicker {
+    stage: Stage,
+    tt_move: Move,
+    killers: [Move; 2],
+    counter_move: Option<Move>,
+    moves: ArrayVec<[ScoredMove; 256]>,
+    bad_noisy: ArrayVec<[ScoredMove; 256]>,
+    index: usize,
+}
+
+impl MovePicker {
+    /// `tt_move` and `killers` may be any moves; they are only played if legal here.
+    pub fn new(tt_move: Move, killers: [Move; 2], counter_move: Option<Move>) -> Self {
+        MovePicker {
+            stage: Stage::TtMove,
+            tt_move,
+            killers,
+            counter_move,
+            moves: ArrayVec::new(),
+            bad_noisy: ArrayVec::new(),
+            index: 0,
+        }
+    }
+
+    /// Captures and queen promotions only, best MVV-LVA first.
+    pub fn qsearch() -> Self {
+        let none: Move = Move { from: Square::A1, to: Square::A1, promotion: None };
+        MovePicker { stage: Stage::QsearchGenerate, ..MovePicker::new(none, [none; 2], None) }
+    }
+
+    fn noisy_score(board: &Board, m: Move) -> i32 {
+        let victim: i32 = if is_capture(board, &m) { MVV_LVA[board.piece_on(m.to).unwrap_or(Piece::Pawn) as usize] } else { 0 };
+        let promotion: i32 = m.promotion.map_or(0, |p| MVV_LVA[p as usize]);
+        (victim + promotion) * 20 - MVV_LVA[board.piece_on(m.from).unwrap() as usize]
+    }
+
+    fn generate_noisy(&mut self, board: &Board) {
+        let enemy: BitBoard = board.colors(!board.side_to_move());
+        let pawns: BitBoard = board.pieces(Piece::Pawn);
+        board.generate_moves(|mut p: PieceMoves| {
+            if !pawns.has(p.from) {
+                p.to &= enemy;
+            }
+            for m in p {
+                if is_noisy(board, &m) && m != self.tt_move {
+                    self.moves.push(ScoredMove::new(m, Self::noisy_score(board, m), board.side_to_move()));
+                }
+            }
+            false
+        });
+    }
+
+    fn generate_quiets(&mut self, board: &Board, history: &HistoryTables, previous: [Option<PieceTo>; 2]) {
+        let enemy: BitBoard = board.colors(!board.side_to_move());
+        let pawns: BitBoard = board.pieces(Piece::Pawn);
+        let side: Color = board.side_to_move();
+        board.generate_moves(|mut p: PieceMoves| {
+            if !pawns.has(p.from) {
+                p.to &= !enemy;
+            }
+            for m in p {
+                if is_noisy(board, &m) || m == self.tt_move || self.killers.contains(&m) || Some(m) == self.counter_move {
+                    continue;
+                }
+                //underpromotions are almost never right, try them last
+                let score: i32 = if m.promotion.is_some() {
+                    -4 * MAX_HISTORY
+                } else {
+                    history.quiet_score(side, m, PieceTo::new(board, m), previous)
+                };
+                self.moves.push(ScoredMove::new(m, score, side));
+            }
+            false
+        });
+    }
+
+    /// Swaps the best remaining move of `moves` to `index` and returns it.
+    fn select(moves: &mut ArrayVec<[ScoredMove; 256]>, index: usize) -> Option<ScoredMove> {
+        let best: usize = (index..moves.len()).max_by_key(|&i| moves[i].key)?;
+        moves.swap(index, best);
+        Some(moves[index])
+    }
+
+    /// A killer or counter move is only searched as such if it is a legal quiet
+    /// move that was not already tried as the TT move.
+    fn is_playable_quiet(&self, board: &Board, m: Move) -> bool {
+        m != self.tt_move && board.is_legal(m) && !is_noisy(board, &m)
+    }
+
+    /// The next move to search, or `None` once every legal move has been returned.
+    /// `previous` holds the moves 1 and 2 plies back, the continuation history keys.
+    pub fn next(&mut self, board: &Board, history: &HistoryTables, previous: [Option<PieceTo>; 2]) -> Option<Move> {
+        loop {
+            match self.stage {
+                Stage::TtMove => {
+                    self.stage = Stage::GenerateNoisy;
+                    if board.is_legal(self.tt_move) {
+                        return Some(self.tt_move);
+                    }
+                },
+                Stage::GenerateNoisy => {
+                    self.generate_noisy(board);
+                    self.stage = Stage::GoodNoisy;
+                },
+                Stage::GoodNoisy => match Self::select(&mut self.moves, self.index) {
+                    Some(scored) => {
+                        self.index += 1;
+                        if see_ge(board, scored.m, 0) {
+                            return Some(scored.m);
+                        }
+                        //losing captures are ordered by how they did before, not by material
+                        let piece_to: PieceTo = PieceTo::new(board, scored.m);
+                        let score: i32 = match is_capture(board, &scored.m) {
+                            true => history.capture_score(piece_to, board.piece_on(scored.m.to).unwrap_or(Piece::Pawn)),
+                            false => 0,
+                        };
+                        self.bad_noisy.push(ScoredMove::new(scored.m, score, board.side_to_move()));
+                    },
+                    None => self.stage = Stage::Killer1,
+                },
+                Stage::Killer1 => {
+                    self.stage = Stage::Killer2;
+                    if self.is_playable_quiet(board, self.killers[0]) {
+                        return Some(self.killers[0]);
+                    }
+                },
+                Stage::Killer2 => {
+                    self.stage = Stage::CounterMove;
+                    if self.killers[1] != self.killers[0] && self.is_playable_quiet(board, self.killers[1]) {
+                        return Some(self.killers[1]);
+                    }
+                },
+                Stage::CounterMove => {
+                    self.stage = Stage::GenerateQuiets;
+                    if let Some(counter_move) = self.counter_move {
+                        if !self.killers.contains(&counter_move) && self.is_playable_quiet(board, counter_move) {
+                            return Some(counter_move);
+                        }
+                    }
+                },
+                Stage::GenerateQuiets => {
+                    self.moves.clear();
+                    self.index = 0;
+                    self.generate_quiets(board, history, previous);
+                    self.stage = Stage::Quiets;
+                },
+                Stage::Quiets => match Self::select(&mut self.moves, self.index) {
+                    Some(scored) => {
+                        self.index += 1;
+                        return Some(scored.m);
+                    },
+                    None => {
+                        self.index = 0;
+                        self.stage = Stage::BadNoisy;
+                    },
+                },
+                Stage::BadNoisy => match Self::select(&mut self.bad_noisy, self.index) {
+                    Some(scored) => {
+                        self.index += 1;
+                        return Some(scored.m);
+                    },
+                    None => self.stage = Stage::Done,
+                },
+                Stage::QsearchGenerate => {
+                    self.generate_noisy(board);
+                    self.stage = Stage::QsearchNoisy;
+                },
+                Stage::QsearchNoisy => match Self::select(&mut self.moves, self.index) {
+                    Some(scored) => {
+                        self.index += 1;
+                        return Some(scored.m);
+                    },
+                    None => self.stage = Stage::Done,
+                },
+                Stage::Done => return None,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn all_moves(board: &Board, tt_move: &str, killer: &str) -> Vec<Move> {
+        let history: HistoryTables = HistoryTables::new();
+        let killers: [Move; 2] = [killer.parse().unwrap(), "h7h8".parse().unwrap()];
+        let mut picker: MovePicker = MovePicker::new(tt_move.parse().unwrap(), killers, None);
+        let mut moves: Vec<Move> = Vec::new();
+        while let Some(m) = picker.next(board, &history, [None, None]) {
+            moves.push(m);
+        }
+        moves
+    }
+
+    #[test]
+    fn every_legal_move_is_returned_once() {
+        let fens: [&str; 3] = [
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+            "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1",
+            "n1n5/PPPk4/8/8/8/8/4Kppp/5N1N b - - 0 1",
+        ];
+        for fen in fens {
+            let board: Board = Board::from_fen(fen, false).unwrap();
+            let mut legal: Vec<Move> = Vec::new();
+            board.generate_moves(|p| {
+                legal.extend(p);
+                false
+            });
+            let mut picked: Vec<Move> = all_moves(&board, "e2a6", "a2a3");
+            assert_eq!(picked.len(), legal.len(), "{}", fen);
+            picked.sort_by_key(|m| m.to_string());
+            picked.dedup();
+            assert_eq!(picked.len(), legal.len(), "{}", fen);
+        }
+    }
+
+    #[test]
+    fn stages_come_in_order() {
+        //Bxa6 is the TT move, then the captures that do not lose material, the killer a2a3,
+        //the quiets, and the losing captures such as Qxf6 last
+        let board: Board = Board::from_fen("r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1", false).unwrap();
+        let moves: Vec<Move> = all_moves(&board, "e2a6", "a2a3");
+        assert_eq!(moves[0], "e2a6".parse().unwrap());
+        let killer: usize = moves.iter().position(|&m| m == "a2a3".parse().unwrap()).unwrap();
+        assert!(moves[1..killer].iter().all(|m| is_capture(&board, m) && see_ge(&board, *m, 0)));
+        assert!(moves[killer + 1..].iter().any(|m| !is_capture(&board, m)));
+        assert!(!see_ge(&board, *moves.last().unwrap(), 0));
+    }
+}
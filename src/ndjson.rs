@@ -0,0 +1,108 @@
+//! Machine-readable search output: one JSON object per line (NDJSON), so tools can
+//! follow a search without parsing the text output. Enabled by the `JSON Output`
+//! UCI option or the `--json` command line flag.
+//!
+//! `--json search` prints only the stream, which ends with a `bestmove` object. Under
+//! UCI the protocol still needs its own `bestmove` line, so the plain line follows
+//! the stream on the same stdout and readers must skip lines that are not JSON.
+use cozy_chess::*;
+use crate::notation::to_san;
+use crate::search::{moves_to_mate, AlphaBetaSearcher};
+use crate::tt::NodeType;
+use std::time::Duration;
+
+/// One line of output: a completed or failed iteration of one MultiPV line, or the
+/// move finally played.
+pub struct Report<'a> {
+    pub kind: &'static str, //"iteration" or "bestmove"
+    pub board: &'a Board,
+    pub depth: i32,
+    pub seldepth: u32,
+    pub multipv: usize, //1-based
+    pub score: i32, //from the side to move's point of view
+    pub bound: NodeType, //aspiration failures report the bound they proved
+    pub nodes: u64,
+    pub elapsed: Duration,
+    pub hashfull: usize, //permille
+    pub pv: &'a [Move], //starts with the reported move
+    pub stats: Option<String>, //counters as a JSON object, with `--features stats`
+}
+
+impl Report<'_> {
+    pub fn to_json(&self) -> String {
+        let (score_type, score_value) = match moves_to_mate(self.score) {
+            Some(moves) => ("mate", moves),
+            None => ("cp", self.score),
+        };
+        let bound: &str = match self.bound {
+            NodeType::Exact => "exact",
+            NodeType::LowerBound => "lowerbound",
+            NodeType::UpperBound => "upperbound",
+        };
+        let (uci, san) = pv_notation(self.board, self.pv);
+        let millis: u128 = self.elapsed.as_millis();
+        let mut json: String = format!(
+            "{{\"type\":\"{}\",\"depth\":{},\"seldepth\":{},\"multipv\":{},\"score\":{{\"type\":\"{}\",\"value\":{}}},\"bound\":\"{}\",\
+             \"nodes\":{},\"nps\":{},\"time\":{},\"hashfull\":{},\"pv\":[{}],\"pv_san\":[{}]",
+            self.kind, self.depth, self.seldepth, self.multipv, score_type, score_value, bound,
+            self.nodes, self.nodes as u128 * 1000 / millis.max(1), millis, self.hashfull, quoted(&uci), quoted(&san),
+        );
+        if self.kind == "bestmove" {
+            json += &format!(",\"bestmove\":\"{}\",\"bestmove_san\":\"{}\"", uci.first().map_or("0000", String::as_str), san.first().map_or("", String::as_str));
+        }
+        if let Some(stats) = &self.stats {
+            json += &format!(",\"stats\":{}", stats);
+        }
+        json + "}"
+    }
+}
+
+/// `pv` in UCI and SAN, each move written in the position it is played from.
+pub fn pv_notation(board: &Board, pv: &[Move]) -> (Vec<String>, Vec<String>) {
+    let mut board: Board = board.clone();
+    let mut uci: Vec<String> = Vec::new();
+    let mut san: Vec<String> = Vec::new();
+    for &m in pv {
+        uci.push(AlphaBetaSearcher::to_standard_uci(&board, m));
+        san.push(to_san(&board, m));
+        board.play(m);
+    }
+    (uci, san)
+}
+
+/// Moves and SAN never contain quotes or backslashes, so no escaping is needed.
+fn quoted(strings: &[String]) -> String {
+    strings.iter().map(|s| format!("\"{}\"", s)).collect::<Vec<String>>().join(",")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::search::MATE_VALUE;
+
+    #[test]
+    fn report_is_one_json_line_with_every_field() {
+        let board: Board = "r3k3/8/8/8/8/8/8/4K2R w K - 0 1".parse().unwrap();
+        let pv: [Move; 2] = ["e1h1".parse().unwrap(), "a8a1".parse().unwrap()];
+        let report: Report = Report {
+            kind: "bestmove",
+            board: &board,
+            depth: 5,
+            seldepth: 9,
+            multipv: 1,
+            score: -MATE_VALUE + 3,
+            bound: NodeType::UpperBound,
+            nodes: 3000,
+            elapsed: Duration::from_millis(1500),
+            hashfull: 12,
+            pv: &pv,
+            stats: None,
+        };
+        assert_eq!(
+            report.to_json(),
+            "{\"type\":\"bestmove\",\"depth\":5,\"seldepth\":9,\"multipv\":1,\"score\":{\"type\":\"mate\",\"value\":-2},\"bound\":\"upperbound\",\
+             \"nodes\":3000,\"nps\":2000,\"time\":1500,\"hashfull\":12,\"pv\":[\"e1g1\",\"a8a1\"],\"pv_san\":[\"O-O\",\"Ra1\"],\
+             \"bestmove\":\"e1g1\",\"bestmove_san\":\"O-O\"}"
+        );
+    }
+}
@@ -0,0 +1,25 @@
+use cozy_chess::*;
+use cozy_chess::util::{display_san_move, parse_san_move, parse_uci_move};
+
+/// Parses a SAN move as written in EPD and PGN files. Annotation glyphs
+/// (`!`, `?`), zero castling (`0-0`) and long algebraic are tolerated.
+pub fn parse_san(board: &Board, san: &str) -> Result<Move, MoveParseError> {
+    let cleaned: String = san
+        .trim()
+        .trim_end_matches(['!', '?'])
+        .trim_end_matches("e.p.")
+        .replace('0', "O");
+    if let Ok(m) = parse_san_move(board, &cleaned) {
+        return Ok(m);
+    }
+    //some suites write their solutions as long algebraic
+    match parse_uci_move(board, san.trim()) {
+        Ok(m) if board.is_legal(m) => Ok(m),
+        _ => Err(MoveParseError),
+    }
+}
+
+/// Formats a legal move in SAN, including the check/mate suffix.
+pub fn to_san(board: &Board, m: Move) -> String {
+    display_san_move(board, m).to_string()
+}
@@ -0,0 +1,402 @@
+use cozy_chess::*;
+use crate::notation::{parse_san, to_san};
+use std::io::Write;
+
+/// A parsed PGN game. Moves are stored as cozy-chess moves so they can be
+/// replayed directly; SAN is regenerated when the game is written.
+pub struct PgnGame {
+    pub tags: Vec<(String, String)>,
+    pub start: Board,
+    pub moves: Vec<PgnMove>,
+    pub result: String,
+}
+
+pub struct PgnMove {
+    pub mv: Move,
+    pub nags: Vec<u8>,
+    pub comment: Option<String>,
+    pub variations: Vec<Vec<PgnMove>>, //alternatives to this move, played from the same position
+}
+
+impl PgnMove {
+    pub fn new(mv: Move) -> Self {
+        PgnMove { mv, nags: Vec::new(), comment: None, variations: Vec::new() }
+    }
+}
+
+impl PgnGame {
+    pub fn new(start: Board) -> Self {
+        PgnGame { tags: Vec::new(), start, moves: Vec::new(), result: "*".to_string() }
+    }
+
+    pub fn tag(&self, name: &str) -> Option<&str> {
+        self.tags.iter().find(|(n, _)| n == name).map(|(_, v)| v.as_str())
+    }
+
+    pub fn set_tag(&mut self, name: &str, value: &str) {
+        match self.tags.iter_mut().find(|(n, _)| n == name) {
+            Some(tag) => tag.1 = value.to_string(),
+            None => self.tags.push((name.to_string(), value.to_string())),
+        }
+    }
+}
+
+#[derive(Clone)]
+enum Token {
+    Tag(String, String),
+    Comment(String),
+    Nag(u8),
+    VariationStart,
+    VariationEnd,
+    Result(String),
+    Word(String),
+}
+
+fn glyph_nag(glyph: &str) -> Option<u8> {
+    match glyph {
+        "!" => Some(1),
+        "?" => Some(2),
+        "!!" => Some(3),
+        "??" => Some(4),
+        "!?" => Some(5),
+        "?!" => Some(6),
+        _ => None,
+    }
+}
+
+fn tokenize(text: &str) -> Vec<Token> {
+    let mut tokens: Vec<Token> = Vec::new();
+    let mut chars = text.chars().peekable();
+    let mut line_start: bool = true;
+    while let Some(c) = chars.next() {
+        match c {
+            '\n' => {
+                line_start = true;
+                continue;
+            },
+            '%' if line_start => {
+                //escape mechanism: the rest of the line is ignored
+                for c in chars.by_ref() {
+                    if c == '\n' {
+                        break;
+                    }
+                }
+            },
+            '[' => {
+                let mut name: String = String::new();
+                while let Some(&c) = chars.peek() {
+                    if c.is_whitespace() || c == '"' || c == ']' {
+                        break;
+                    }
+                    name.push(c);
+                    chars.next();
+                }
+                let mut value: String = String::new();
+                let mut in_value: bool = false;
+                while let Some(c) = chars.next() {
+                    match c {
+                        '"' => in_value = !in_value,
+                        '\\' if in_value => value.extend(chars.next()),
+                        ']' if !in_value => break,
+                        c if in_value => value.push(c),
+                        _ => (),
+                    }
+                }
+                tokens.push(Token::Tag(name, value));
+            },
+            '{' => {
+                let comment: String = chars.by_ref().take_while(|&c| c != '}').collect();
+                tokens.push(Token::Comment(comment.split_whitespace().collect::<Vec<&str>>().join(" ")));
+            },
+            ';' => {
+                let comment: String = chars.by_ref().take_while(|&c| c != '\n').collect();
+                tokens.push(Token::Comment(comment.trim().to_string()));
+                line_start = true;
+                continue;
+            },
+            '(' => tokens.push(Token::VariationStart),
+            ')' => tokens.push(Token::VariationEnd),
+            '$' => {
+                let mut digits: String = String::new();
+                while let Some(&c) = chars.peek() {
+                    if !c.is_ascii_digit() {
+                        break;
+                    }
+                    digits.push(c);
+                    chars.next();
+                }
+                if let Ok(nag) = digits.parse() {
+                    tokens.push(Token::Nag(nag));
+                }
+            },
+            c if c.is_whitespace() => (),
+            c => {
+                let mut word: String = c.to_string();
+                while let Some(&c) = chars.peek() {
+                    if c.is_whitespace() || "{}()[];$".contains(c) {
+                        break;
+                    }
+                    word.push(c);
+                    chars.next();
+                }
+                match word.as_str() {
+                    "1-0" | "0-1" | "1/2-1/2" | "*" => tokens.push(Token::Result(word)),
+                    _ => {
+                        //move numbers ("12." or "12...") carry no information; castling may be written 0-0
+                        let digits: usize = word.len() - word.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+                        let word: &str = if word[digits..].starts_with('.') { word[digits..].trim_start_matches('.') } else { &word };
+                        let glyphs: &str = &word[word.trim_end_matches(['!', '?']).len()..];
+                        let san: &str = &word[..word.len() - glyphs.len()];
+                        if !san.is_empty() {
+                            tokens.push(Token::Word(san.to_string()));
+                        }
+                        if let Some(nag) = glyph_nag(glyphs) {
+                            tokens.push(Token::Nag(nag));
+                        }
+                    },
+                }
+            },
+        }
+        line_start = false;
+    }
+    tokens
+}
+
+struct Line {
+    board: Board, //position after the last move of this line
+    before: Board, //position before the last move, where a variation would start
+    moves: Vec<PgnMove>,
+}
+
+/// The game being read: its tags, then the main line and any open variations.
+#[derive(Default)]
+struct GameParser {
+    tags: Vec<(String, String)>,
+    lines: Vec<Line>, //empty while reading tags
+    start: Board,
+}
+
+impl GameParser {
+    /// Feeds one token, returning the game once its result is read.
+    fn token(&mut self, token: Token) -> Result<Option<PgnGame>, String> {
+        if self.lines.is_empty() {
+            //tag section; the first movetext token starts the game
+            if let Token::Tag(name, value) = token {
+                self.tags.push((name, value));
+                return Ok(None);
+            }
+            self.start = match self.tags.iter().find(|(n, _)| n == "FEN") {
+                Some((_, fen)) => Board::from_fen(fen, false).map_err(|e| format!("invalid FEN {}: {:?}", fen, e))?,
+                None => Board::default(),
+            };
+            self.lines.push(Line { board: self.start.clone(), before: self.start.clone(), moves: Vec::new() });
+        }
+        let lines: &mut Vec<Line> = &mut self.lines;
+        match token {
+            Token::Word(san) => {
+                let line: &mut Line = lines.last_mut().unwrap();
+                let m: Move = parse_san(&line.board, &san).map_err(|_| format!("illegal move {} in position {}", san, line.board))?;
+                line.before = line.board.clone();
+                line.board.play(m);
+                line.moves.push(PgnMove::new(m));
+            },
+            Token::Comment(comment) => {
+                if let Some(last) = lines.last_mut().unwrap().moves.last_mut() {
+                    last.comment = Some(match last.comment.take() {
+                        Some(previous) => format!("{} {}", previous, comment),
+                        None => comment,
+                    });
+                }
+            },
+            Token::Nag(nag) => {
+                if let Some(last) = lines.last_mut().unwrap().moves.last_mut() {
+                    last.nags.push(nag);
+                }
+            },
+            Token::VariationStart => {
+                let before: Board = lines.last().unwrap().before.clone();
+                lines.push(Line { board: before.clone(), before, moves: Vec::new() });
+            },
+            Token::VariationEnd => {
+                if lines.len() < 2 {
+                    return Err("unbalanced ')' in movetext".to_string());
+                }
+                let variation: Line = lines.pop().unwrap();
+                match lines.last_mut().unwrap().moves.last_mut() {
+                    Some(last) => last.variations.push(variation.moves),
+                    None => return Err("variation before the first move".to_string()),
+                }
+            },
+            Token::Result(result) => {
+                if lines.len() != 1 {
+                    return Err("unterminated variation".to_string());
+                }
+                let main: Line = lines.pop().unwrap();
+                let mut game: PgnGame = PgnGame::new(self.start.clone());
+                game.tags = std::mem::take(&mut self.tags);
+                game.moves = main.moves;
+                game.result = result;
+                return Ok(Some(game));
+            },
+            Token::Tag(..) => return Err("tag inside movetext".to_string()),
+        }
+        Ok(None)
+    }
+}
+
+/// Parses every game in a PGN file, failing on the first malformed one.
+pub fn parse_pgn(text: &str) -> Result<Vec<PgnGame>, String> {
+    parse_pgn_games(text).into_iter().collect()
+}
+
+/// Parses every game in a PGN file on its own. A malformed game gives an error and
+/// the rest of its movetext is skipped, up to its result or the next game's tags.
+pub fn parse_pgn_games(text: &str) -> Vec<Result<PgnGame, String>> {
+    let mut games: Vec<Result<PgnGame, String>> = Vec::new();
+    let mut parser: GameParser = GameParser::default();
+    let mut skipping: bool = false;
+    for token in tokenize(text) {
+        if skipping {
+            match token {
+                Token::Result(_) => {
+                    skipping = false;
+                    continue;
+                },
+                Token::Tag(..) => skipping = false,
+                _ => continue,
+            }
+        }
+        let tag: Option<Token> = matches!(token, Token::Tag(..)).then(|| token.clone());
+        let ends_game: bool = matches!(token, Token::Result(_));
+        match parser.token(token) {
+            Ok(Some(game)) => games.push(Ok(game)),
+            Ok(None) => (),
+            Err(e) => {
+                games.push(Err(e));
+                parser = GameParser::default();
+                //a tag in movetext starts the next game; anything else skips to the end of this one
+                match tag {
+                    Some(tag) => {
+                        let _ = parser.token(tag);
+                    },
+                    None => skipping = !ends_game,
+                }
+            },
+        }
+    }
+    games
+}
+
+fn movetext_tokens(board: &Board, moves: &[PgnMove], tokens: &mut Vec<String>) {
+    let mut board: Board = board.clone();
+    let mut needs_number: bool = true;
+    for m in moves {
+        if board.side_to_move() == Color::White {
+            tokens.push(format!("{}.", board.fullmove_number()));
+        } else if needs_number {
+            tokens.push(format!("{}...", board.fullmove_number()));
+        }
+        tokens.push(to_san(&board, m.mv));
+        needs_number = false;
+        for nag in &m.nags {
+            tokens.push(format!("${}", nag));
+        }
+        if let Some(comment) = &m.comment {
+            tokens.push(format!("{{{}}}", comment));
+            needs_number = true;
+        }
+        for variation in &m.variations {
+            tokens.push("(".to_string());
+            movetext_tokens(&board, variation, tokens);
+            tokens.push(")".to_string());
+            needs_number = true;
+        }
+        board.play(m.mv);
+    }
+}
+
+/// Writes a game in export format: tags, then movetext wrapped at 80 columns.
+pub fn write_pgn(out: &mut impl Write, game: &PgnGame) -> std::io::Result<()> {
+    for (name, value) in &game.tags {
+        writeln!(out, "[{} \"{}\"]", name, value.replace('\\', "\\\\").replace('"', "\\\""))?;
+    }
+    writeln!(out)?;
+    let mut tokens: Vec<String> = Vec::new();
+    movetext_tokens(&game.start, &game.moves, &mut tokens);
+    tokens.push(game.result.clone());
+    let mut line: String = String::new();
+    for token in tokens {
+        if !line.is_empty() && line.len() + token.len() >= 80 {
+            writeln!(out, "{}", line)?;
+            line.clear();
+        }
+        if !line.is_empty() && !line.ends_with('(') && token != ")" {
+            line.push(' ');
+        }
+        line.push_str(&token);
+    }
+    writeln!(out, "{}\n", line)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GAME: &str = r#"[Event "Test"]
+[White "A \"quoted\" name"]
+[Result "1-0"]
+
+1. e4 e5 2. Nf3 {main line} (2. f4?! exf4 3. Nf3 (3. Bc4 Qh4+) g5) 2... Nc6 $1
+3. Bb5 a6 ; Morphy defence
+4. Ba4 Nf6 5. O-O 1-0
+"#;
+
+    #[test]
+    fn parses_tags_comments_nags_and_variations() {
+        let games: Vec<PgnGame> = parse_pgn(GAME).unwrap();
+        assert_eq!(games.len(), 1);
+        let game: &PgnGame = &games[0];
+        assert_eq!(game.tags[1], ("White".to_string(), "A \"quoted\" name".to_string()));
+        assert_eq!(game.result, "1-0");
+        assert_eq!(game.moves.len(), 9);
+        assert_eq!(game.moves[2].comment.as_deref(), Some("main line"));
+        assert_eq!(game.moves[2].variations.len(), 1);
+        let variation: &Vec<PgnMove> = &game.moves[2].variations[0];
+        assert_eq!(variation[0].nags, vec![6]);
+        assert_eq!(variation[2].variations.len(), 1);
+        assert_eq!(game.moves[3].nags, vec![1]);
+        assert_eq!(game.moves[5].comment.as_deref(), Some("Morphy defence"));
+        assert_eq!(game.moves[8].mv, "e1h1".parse().unwrap());
+    }
+
+    #[test]
+    fn write_then_parse_round_trips() {
+        let games: Vec<PgnGame> = parse_pgn(GAME).unwrap();
+        let mut out: Vec<u8> = Vec::new();
+        write_pgn(&mut out, &games[0]).unwrap();
+        let text: String = String::from_utf8(out).unwrap();
+        assert!(text.replace('\n', " ").contains("2. Nf3 {main line} (2. f4 $6 exf4 3. Nf3 (3. Bc4 Qh4+) 3... g5) 2... Nc6 $1"));
+        let again: Vec<PgnGame> = parse_pgn(&text).unwrap();
+        assert_eq!(again[0].tags, games[0].tags);
+        let moves = |game: &PgnGame| game.moves.iter().map(|m| m.mv).collect::<Vec<Move>>();
+        assert_eq!(moves(&again[0]), moves(&games[0]));
+    }
+
+    #[test]
+    fn castling_with_zeros_is_not_a_move_number() {
+        let games: Vec<PgnGame> = parse_pgn("1. e4 e5 2. Nf3 Nc6 3. Bc4 Bc5 4. 0-0 *").unwrap();
+        assert_eq!(games[0].moves.len(), 7);
+        assert_eq!(games[0].moves[6].mv, "e1h1".parse().unwrap());
+    }
+
+    #[test]
+    fn malformed_games_are_reported_and_skipped() {
+        let text: &str = "1. e4 Ke7 Qxf7 (1... e5) 1-0\n[Event \"missing result\"]\n1. d4\n[Event \"good\"]\n1. c4 *\n1. e4 ) *\n1. Nf3 *";
+        let games: Vec<Result<PgnGame, String>> = parse_pgn_games(text);
+        assert_eq!(games.len(), 5);
+        assert!(games[0].is_err() && games[1].is_err() && games[3].is_err());
+        assert_eq!(games[2].as_ref().unwrap().tag("Event"), Some("good"));
+        assert_eq!(games[4].as_ref().unwrap().moves[0].mv, "g1f3".parse().unwrap());
+        assert!(parse_pgn(text).is_err());
+    }
+}
@@ -1,33 +1,154 @@
 use arrayvec::ArrayVec;
 use cozy_chess::*;
-use crate::constants::*;
 use crate::evaluation::*;
+use crate::history::*;
+use crate::movepick::*;
+use crate::ndjson::{pv_notation, Report};
+use crate::see::see_ge;
+use crate::strength::*;
+use crate::tt::*;
+use crate::tunables::*;
 use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
 use std::time::{Duration, Instant};
-const TT_SIZE: usize = 1 << 24;
+#[cfg(feature = "stats")]
+use crate::stats::SearchStats;
+
+/// Bumps a `SearchStats` counter; compiles to nothing without the `stats` feature.
+macro_rules! stat {
+    ($counter:expr) => {
+        #[cfg(feature = "stats")]
+        {
+            $counter += 1;
+        }
+    };
+}
+
+/// Score of a mate at the root; mates further away score `MATE_VALUE - ply`.
+pub const MATE_VALUE: i32 = 1 << 30;
+/// Bound outside every possible score, used for full-window searches.
+const SCORE_INFINITY: i32 = MATE_VALUE + 1;
+/// Deepest ply the search stack holds. Extensions can carry a line past the
+/// nominal depth, so `pvs` stops and returns the static eval here.
+const MAX_PLY: usize = 128;
+/// How a verbose search reports its iterations.
+#[derive(Clone, Copy, Default, PartialEq)]
+pub enum InfoFormat {
+    #[default]
+    Uci,
+    Xboard, //CECP thinking output, `ply score centiseconds nodes pv`
+    Json, //NDJSON, see `ndjson::Report`
+}
+/// Mixed into the TT key of a search that excludes `m`.
+fn excluded_move_key(m: Move) -> u64 {
+    let index: u64 = (m.from as u64 * 64 + m.to as u64) * 8 + m.promotion.map_or(7, |p| p as u64);
+    (index + 1).wrapping_mul(0x9E3779B97F4A7C15)
+}
+/// Moves until mate for a mate score, negative when the side to move gets mated.
+pub fn moves_to_mate(score: i32) -> Option<i32> {
+    (score.abs() >= MATE_VALUE - 1000).then(|| score.signum() * ((MATE_VALUE - score.abs() + 1) / 2))
+}
+/// A score as XBoard shows it: centipawns, or 100000 plus the moves to mate.
+fn xboard_score(score: i32) -> i32 {
+    match moves_to_mate(score) {
+        Some(moves) => moves.signum() * 100000 + moves,
+        None => score,
+    }
+}
+/// Late move reductions are computed in 1/1024 ply. The base reduction of the
+/// move searched `i`-th at `depth` is lmr_base + ln(depth) * ln(i) / lmr_divisor.
+fn lmr_table() -> [[i32; 64]; 64] {
+    let mut table: [[i32; 64]; 64] = [[0; 64]; 64];
+    for (depth, row) in table.iter_mut().enumerate().skip(1) {
+        for (i, reduction) in row.iter_mut().enumerate().skip(1) {
+            *reduction = lmr_base() + ((depth as f64).ln() * (i as f64).ln() * 100.0 / lmr_divisor() as f64 * 1024.0) as i32;
+        }
+    }
+    table
+}
 pub struct AlphaBetaSearcher {
-    transposition_table: Vec<TTEntry>,
+    transposition_table: TranspositionTable,
     root_best_move: Move,
     root_score: i32,
     min_val: i32,
     nodes: u64,
-    killer_table: [Move; 128],
-    history_table: [[[i32; 64]; 64]; 2],
+    node_limit: u64,
+    stop: Arc<AtomicBool>, //set by another thread to end the search early
+    stoppable: bool, //false during depth 1, which always completes
+    verbose: bool,
+    info_format: InfoFormat,
+    seldepth: u32, //deepest ply reached by the current search
+    history: HistoryTables,
+    correction: CorrectionHistory,
+    stack: [SearchStack; MAX_PLY],
+    lmr_table: [[i32; 64]; 64], //base reduction by depth and move number
     threefold_repetition: Vec<u64>, //keep a running stack of boards seen in the DFS
+    root_excluded: Vec<Move>, //root moves already reported as a better MultiPV line
+    strength: StrengthSettings,
+    eval_noise: i32, //max cp added to or removed from static evals, 0 at full strength
+    noise_seed: u64,
+    #[cfg(feature = "stats")]
+    stats: SearchStats,
 }
+/// Per-ply search state, indexed by ply. An entry is filled in by the node at
+/// that ply, so children can look at their parent's and grandparent's state.
 #[derive(Clone, Copy)]
-struct TTEntry { // 16 bytes total
-    hash: u64, //4 bytes
-    depth: i32, //2 bytes
-    score: i32, //2 bytes
-    best_move: Move, // 8 bytes
-    node_type: NodeType,
+struct SearchStack {
+    static_eval: i32, //meaningless when in_check
+    in_check: bool,
+    piece_to: Option<PieceTo>, //move played from this ply, None for a null move
+    excluded: Option<Move>, //set while this node runs its singular extension verification search
+    killers: [Move; 2],
+    cut_node: bool, //set by the parent: a non-PV node expected to fail high
 }
+
+impl Default for SearchStack {
+    fn default() -> Self {
+        SearchStack {
+            static_eval: 0,
+            in_check: false,
+            piece_to: None,
+            excluded: None,
+            killers: [Move::from_str("a1a1").unwrap(); 2],
+            cut_node: false,
+        }
+    }
+}
+/// Stop conditions for a single search. Unset fields do not limit the search.
+#[derive(Clone, Copy, Default)]
+pub struct SearchLimits {
+    pub time_remaining: Option<u64>, //clock time in ms, budgeted the same way as `go wtime`
+    pub increment: Option<u64>, //ms added to the clock after each move
+    pub moves_to_go: Option<u32>, //moves until the clock is topped up, the rest of the game if unset
+    pub movetime: Option<u64>, //fixed ms for this move
+    pub depth: Option<i32>,
+    pub nodes: Option<u64>,
+    pub multipv: usize, //number of best root moves to report, 0 and 1 both mean a single line
+}
+/// One completed iterative deepening iteration.
+#[derive(Clone, Copy)]
+pub struct IterationInfo {
+    pub depth: i32,
+    pub best_move: Move,
+    pub elapsed: Duration,
+}
+/// One MultiPV line: a root move and its score from the side to move's point of view.
 #[derive(Clone, Copy)]
-enum NodeType {
-    Exact,
-    LowerBound,
-    UpperBound,
+pub struct PvLine {
+    pub best_move: Move,
+    pub score: i32,
+}
+pub struct SearchResult {
+    pub best_move: Move,
+    pub score: i32,
+    pub depth: i32,
+    pub nodes: u64,
+    pub elapsed: Duration,
+    pub iterations: Vec<IterationInfo>,
+    pub lines: Vec<PvLine>, //MultiPV lines of the last completed iteration, best first
+    #[cfg(feature = "stats")]
+    pub stats: SearchStats,
 }
 
 
@@ -36,20 +157,26 @@ impl AlphaBetaSearcher {
         AlphaBetaSearcher {
             root_best_move: Move::from_str("a1a1").unwrap(),
             root_score: 0,
-            min_val: - (1 << 30),
-            transposition_table: vec![TTEntry {
-                hash: 0,
-                depth: 0,
-                score: 0,
-                best_move: Move::from_str("a1a1").unwrap(),
-                node_type: NodeType::Exact,
-            }; TT_SIZE],
-            // killer_table: vec![Move::from_str("a1a1").unwrap(); 128],
-            // history_table: vec![vec![vec![0; 64]; 64]; 2],
-            killer_table: [Move::from_str("a1a1").unwrap(); 128],
-            history_table: [[[0; 64]; 64]; 2],
+            min_val: -MATE_VALUE,
+            transposition_table: TranspositionTable::new(DEFAULT_HASH_MB),
+            history: HistoryTables::new(),
+            correction: CorrectionHistory::new(),
+            stack: [SearchStack::default(); MAX_PLY],
+            lmr_table: lmr_table(),
             threefold_repetition: Vec::new(),
+            root_excluded: Vec::new(),
+            strength: StrengthSettings::default(),
+            eval_noise: 0,
+            noise_seed: 0,
+            #[cfg(feature = "stats")]
+            stats: SearchStats::default(),
             nodes: 0,
+            node_limit: u64::MAX,
+            stop: Arc::new(AtomicBool::new(false)),
+            stoppable: false,
+            verbose: true,
+            info_format: InfoFormat::Uci,
+            seldepth: 0,
         }
     }
     pub fn add_to_threefold_repetition(&mut self, hash: u64) {
@@ -58,8 +185,62 @@ impl AlphaBetaSearcher {
     pub fn clear_threefold_repetition(&mut self) {
         self.threefold_repetition = Vec::new();
     }
+    /// Forgets what was learned about the previous game; move ordering tables are reset per search anyway.
+    pub fn new_game(&mut self) {
+        self.transposition_table.clear();
+        self.correction.clear();
+    }
+    pub fn clear_hash(&mut self) {
+        self.transposition_table.clear();
+    }
+    /// Takes effect lazily: the table is reallocated, empty, by `allocate_hash` or the next search.
+    pub fn set_hash_size(&mut self, size_mb: usize) {
+        self.transposition_table.resize(size_mb);
+    }
+    pub fn allocate_hash(&mut self) {
+        self.transposition_table.allocate();
+    }
+    /// Writes the transposition table to `path`, see `TranspositionTable::save`.
+    pub fn save_hash(&self, path: &str) -> std::io::Result<usize> {
+        self.transposition_table.save(path)
+    }
+    pub fn load_hash(&mut self, path: &str) -> std::io::Result<usize> {
+        self.transposition_table.load(path)
+    }
+    /// A flag another thread can set to end the running search once depth 1 is done.
+    /// The searcher never clears it; whoever sets it clears it before the next search.
+    pub fn stop_signal(&self) -> Arc<AtomicBool> {
+        Arc::clone(&self.stop)
+    }
+    pub fn set_verbose(&mut self, verbose: bool) {
+        self.verbose = verbose;
+    }
+    pub fn set_info_format(&mut self, info_format: InfoFormat) {
+        self.info_format = info_format;
+    }
+    pub fn info_format(&self) -> InfoFormat {
+        self.info_format
+    }
+    /// Searches under a limited strength store noisy evals and scores, so the TT and
+    /// correction history are cleared whenever the limit changes.
+    pub fn set_strength(&mut self, strength: StrengthSettings) {
+        if strength.limit() != self.strength.limit() {
+            self.new_game();
+        }
+        self.strength = strength;
+    }
+
+    fn evaluate(&self, board: &Board) -> i32 {
+        let eval: i32 = pesto_evaluate_from_scratch(board);
+        if self.eval_noise == 0 {
+            return eval;
+        }
+        //hash the position so a node gets the same noise every time it is searched
+        let h: u64 = (board.hash() ^ self.noise_seed).wrapping_mul(0x9E3779B97F4A7C15);
+        eval + ((h >> 33) % (2 * self.eval_noise as u64 + 1)) as i32 - self.eval_noise
+    }
 
-    fn to_standard_uci(board: &Board, m: Move) -> String {
+    pub fn to_standard_uci(board: &Board, m: Move) -> String {
         // cozy-chess encodes castling as king-to-rook (e1h1). Match runners expect e1g1.
         if board.piece_on(m.from) == Some(Piece::King)
             && board.color_on(m.to) == Some(board.side_to_move())
@@ -82,94 +263,35 @@ impl AlphaBetaSearcher {
         m.to_string()
     }
 
-    fn move_is_capture(&self, board: &Board, m: &Move) -> bool {
-        if board.piece_on(m.to).is_some() {
-            return true;
-        }
-        // en passant: pawn captures onto the empty EP square
-        if let Some(ep_file) = board.en_passant() {
-            if m.to.file() == ep_file
-                && m.from.file() != m.to.file()
-                && board.piece_on(m.from) == Some(Piece::Pawn)
-            {
-                return true;
-            }
-        }
-        false
-    }
-
     fn captured_piece_value(&self, board: &Board, m: Move) -> i32 {
         if let Some(piece) = board.piece_on(m.to) {
             return self.piece_value(piece);
         }
-        if self.move_is_capture(board, &m) {
+        if is_capture(board, &m) {
             return self.piece_value(Piece::Pawn);
         }
         0
     }
 
-    fn mvv_lva_value(&self, piece: Piece) -> i32 {
-        match piece {
-            Piece::Pawn => 1,
-            Piece::Knight => 3,
-            Piece::Bishop => 3,
-            Piece::Rook => 5,
-            Piece::Queen => 9,
-            _ => 0,
-        }
-    }
-
-    fn score_moves(&self, _board: &Board, moves: &ArrayVec<[Move; 256]>, tt_move: Move, ply: u32) -> Vec<i32> {
-        //take in a board and a list of moves and return a list of scores for each move
-        let mut scores: Vec<i32> = Vec::new();
-        scores.reserve(moves.len());
-        for m in moves {
-            let mut score: i32 = 0;
-            if *m == tt_move {
-                score += TT_BONUS;
-            }
-            // Most valuable victim - least valuable attacker
-            if self.move_is_capture(_board, m) {
-                let attacker: Piece = _board.piece_on(m.from).unwrap();
-                let target_value: i32 = match _board.piece_on(m.to) {
-                    Some(piece) => self.mvv_lva_value(piece),
-                    None => self.mvv_lva_value(Piece::Pawn), // en passant
-                };
-                score += target_value * 20 - self.mvv_lva_value(attacker) + CAPTURE_BONUS;
-            }
-            else if *m == self.killer_table[ply as usize] {
-                score += KILLER_BONUS; //TODO: revisit this constant
-            }
-            else {
-                score += self.history_table[_board.side_to_move() as usize][m.from as usize][m.to as usize];
-            }
-            //idea: malus for underpromotions
-            if m.promotion.is_some() {
-                if m.promotion.unwrap() != Piece::Queen {
-                    score -= CAPTURE_BONUS * 2;
-                }
-                else {
-                    score += CAPTURE_BONUS;
-                
-                }
-            }
-            scores.push(score);
-        }
-        scores
+    /// Whether the current iteration must be abandoned: out of time or nodes, or stopped.
+    fn out_of_budget(&self, start_time: Instant, time_limit: Duration) -> bool {
+        start_time.elapsed() > time_limit || self.nodes > self.node_limit || (self.stoppable && self.stop.load(Ordering::Relaxed))
     }
 
-    fn sort_moves(&self, moves: &mut ArrayVec<[Move; 256]>, scores: &mut Vec<i32>) {
-        let mut i = 1;
-        while i < moves.len() {
-            let mut j = i;
-            while j > 0 && scores[j] > scores[j - 1] {
-                scores.swap(j, j - 1);
-                moves.swap(j, j - 1);
-                j -= 1;
-            }
-            i += 1;
-        }
+    /// Moves played 1 and 2 plies before `ply`, the keys of continuation history.
+    fn previous_moves(&self, ply: u32) -> [Option<PieceTo>; 2] {
+        let ply: usize = ply as usize;
+        [
+            if ply >= 1 { self.stack[ply - 1].piece_to } else { None },
+            if ply >= 2 { self.stack[ply - 2].piece_to } else { None },
+        ]
     }
+
+    /// Piece taken by a capture, a pawn for en passant.
+    fn captured_piece(&self, board: &Board, m: Move) -> Piece {
+        board.piece_on(m.to).unwrap_or(Piece::Pawn)
+    }
+
     fn piece_value(&self, piece: Piece) -> i32 {
         match piece {
             Piece::Pawn => 100,
@@ -180,59 +302,98 @@ impl AlphaBetaSearcher {
             Piece::King => 20000,
         }
     }
-    fn see_worst_case(&self, b: &Board, m: Move) -> i32 {
-        //assume piece will make move and immediately be lost for nothing
-        let cap_value: i32 = self.captured_piece_value(b, m);
-        let attacker_value: i32 = self.piece_value(b.piece_on(m.from).unwrap());
-        cap_value - attacker_value
-    }
-    fn quiesce(&mut self, board: &Board, alpha: i32, beta: i32, ply: u32, start_time: Instant, time_limit: Duration) -> i32 {
-        //quiesce the position
+    /// Searches captures and queen promotions until the position is quiet. The side to
+    /// move may stand pat on the static eval, except when in check at the first qsearch
+    /// ply (`qply == 0`), where every evasion is searched so mates are seen. Results are
+    /// stored in the TT at depth 0.
+    #[allow(clippy::too_many_arguments)]
+    fn quiesce(&mut self, board: &Board, alpha: i32, beta: i32, ply: u32, qply: u32, start_time: Instant, time_limit: Duration) -> i32 {
         self.nodes += 1;
-        let stand_pat: i32 = pesto_evaluate_from_scratch(board);
-        if stand_pat >= beta {
-            return beta;
-        }
-        if start_time.elapsed() > time_limit {
+        self.seldepth = self.seldepth.max(ply);
+        stat!(self.stats.qnodes);
+        if self.out_of_budget(start_time, time_limit) {
             return self.min_val;
         }
-
-        let mut local_alpha: i32 = alpha.max(stand_pat);
-        let mut moves = ArrayVec::<[Move; 256]>::new();
-        board.generate_moves(|p: PieceMoves| {
-            for m in p {
-                if self.move_is_capture(board, &m) {
-                    moves.push(m);
+        let tt_key: u64 = board.hash();
+        let entry: TTEntry = self.transposition_table.probe(tt_key);
+        let tt_hit: bool = entry.hash == tt_key;
+        if tt_hit {
+            match entry.node_type {
+                NodeType::Exact => return entry.score,
+                NodeType::LowerBound if entry.score >= beta => return entry.score,
+                NodeType::UpperBound if entry.score <= alpha => return entry.score,
+                _ => (),
+            }
+        }
+        let evasions: bool = qply == 0 && !board.checkers().is_empty();
+        //pvs stores no real static eval for a position in check
+        let raw_eval: i32 = if tt_hit && board.checkers().is_empty() { entry.static_eval } else { self.evaluate(board) };
+        let stand_pat: i32 = raw_eval + self.correction.correction(board);
+        let mut best_score: i32 = self.min_val + ply as i32; //mated if no evasion is found
+        let mut alpha: i32 = alpha;
+        if !evasions {
+            if stand_pat >= beta {
+                return stand_pat;
+            }
+            best_score = stand_pat;
+            alpha = alpha.max(stand_pat);
+        }
+        let original_alpha: i32 = alpha;
+        let none: Move = Move::from_str("a1a1").unwrap();
+        let mut best_move: Move = none;
+        let mut picker: MovePicker = if evasions { MovePicker::new(none, [none; 2], None) } else { MovePicker::qsearch() };
+        while let Some(m) = picker.next(board, &self.history, [None, None]) {
+            if !evasions {
+                //delta pruning: even winning the piece for free would not reach alpha
+                if m.promotion.is_none() && stand_pat + self.captured_piece_value(board, m) + delta_margin() <= alpha {
+                    continue;
+                }
+                if !see_ge(board, m, 0) {
+                    continue;
                 }
             }
-            false
-        });
-        //sort moves
-        let mut scores: Vec<i32> = self.score_moves(board, &moves, Move::from_str("a1a1").unwrap(), ply);
-        self.sort_moves(&mut moves, &mut scores);
-        let mut best_score: i32 = -self.min_val;
-        for m in moves {
             let mut new_board: Board = board.clone();
-            let worst_case: i32 = self.see_worst_case(&new_board, m);
-            let at_least: i32 = stand_pat + worst_case;
-            if at_least > beta {
-                return beta;
-            }
             new_board.play(m);
-            let score: i32 = -self.quiesce(&new_board, -beta, -local_alpha, ply + 1, start_time, time_limit);
-            if score >= beta {
-                return beta;
-            }
+            let score: i32 = -self.quiesce(&new_board, -beta, -alpha, ply + 1, qply + 1, start_time, time_limit);
             if score > best_score {
                 best_score = score;
-                local_alpha = local_alpha.max(score);
+                best_move = m;
+                if score >= beta {
+                    break;
+                }
+                alpha = alpha.max(score);
             }
         }
-        local_alpha
+        if best_score.abs() == self.min_val.abs() {
+            return best_score; //out of time
+        }
+        let node_type: NodeType = if best_score >= beta {
+            NodeType::LowerBound
+        } else if best_score > original_alpha {
+            NodeType::Exact
+        } else {
+            NodeType::UpperBound
+        };
+        //a depth 0 result must not replace what the main search learned about this position
+        if !(tt_hit && entry.depth > 0) {
+            self.transposition_table.store(TTEntry {
+                hash: tt_key,
+                depth: 0,
+                score: best_score,
+                static_eval: raw_eval,
+                best_move,
+                node_type,
+            });
+        }
+        best_score
     }
 
+    /// A singular extension verification search skips the stack's excluded move. Such
+    /// a search reads and writes the TT under its own key so it never meets the real entry.
+    #[allow(clippy::too_many_arguments)]
     fn pvs(&mut self, board: &Board, depth: i32, alpha: i32, beta: i32, ply:u32, start_time: Instant, time_limit: Duration, can_null: bool) -> i32 {
         self.nodes += 1;
+        self.seldepth = self.seldepth.max(ply);
         if board.status() != GameStatus::Ongoing {
             match board.status() {
                 GameStatus::Won => return self.min_val + (ply as i32),
@@ -253,134 +414,251 @@ impl AlphaBetaSearcher {
                 return 0;
             }
         }
+        if ply as usize >= MAX_PLY - 1 {
+            return self.evaluate(board);
+        }
 
 
         //check extension: if in check, increase depth by 1
         let mut depth_modifier: i32 = 0;
-        let in_check: bool = board.checkers().len() > 0;
+        let in_check: bool = !board.checkers().is_empty();
         if in_check  && !root{
             depth_modifier += 1;
         }
 
         if depth + depth_modifier <= 0 {
-            return self.quiesce(board, alpha, beta, ply, start_time, time_limit);
+            return self.quiesce(board, alpha, beta, ply, 0, start_time, time_limit);
         }
-        if start_time.elapsed() > time_limit {
+        if self.out_of_budget(start_time, time_limit) {
             return self.min_val;
         }
-        let pv_node: bool = beta - alpha > 1;
+        let pv_node: bool = beta.saturating_sub(alpha) > 1;
+        let excluded: Option<Move> = self.stack[ply as usize].excluded;
+        let cut_node: bool = self.stack[ply as usize].cut_node && !pv_node;
         // probe TT
         let mut best_score: i32 = self.min_val;
         let mut new_alpha: i32 = alpha;
         let mut new_beta: i32 = beta;
-        let entry: TTEntry = self.transposition_table[board.hash() as usize % TT_SIZE];
-        let tt_hit: bool = entry.hash == board.hash();
+        let tt_key: u64 = board.hash() ^ excluded.map_or(0, excluded_move_key);
+        let entry: TTEntry = self.transposition_table.probe(tt_key);
+        let tt_hit: bool = entry.hash == tt_key;
         let tt_move: Move = if tt_hit { entry.best_move } else { Move::from_str("a1a1").unwrap() };
+        stat!(self.stats.tt_probes[pv_node as usize]);
+        if tt_hit {
+            stat!(self.stats.tt_hits[pv_node as usize]);
+        }
         if tt_hit && entry.depth >= depth && !root && !pv_node {
             match entry.node_type {
-                NodeType::Exact => return entry.score,
+                NodeType::Exact => {
+                    stat!(self.stats.tt_cutoffs);
+                    return entry.score;
+                },
                 NodeType::LowerBound => new_alpha = alpha.max(entry.score),
                 NodeType::UpperBound => new_beta = beta.min(entry.score),
             }
             if new_alpha >= new_beta {
+                stat!(self.stats.tt_cutoffs);
                 return entry.score;
             }
         }
+        //the TT keeps the raw eval, the correction is applied fresh on every visit
+        let raw_eval: i32 = if in_check {
+            0
+        } else if tt_hit {
+            entry.static_eval
+        } else {
+            self.evaluate(board)
+        };
+        let stand_pat: i32 = if in_check { 0 } else { raw_eval + self.correction.correction(board) };
+        self.stack[ply as usize].static_eval = stand_pat;
+        self.stack[ply as usize].in_check = in_check;
+        //improving: our static eval is better than two plies ago, so prune less on the fail-low side and more on the fail-high side
+        let improving: bool = !in_check && (ply < 2 || {
+            let grandparent: &SearchStack = &self.stack[ply as usize - 2];
+            grandparent.in_check || stand_pat > grandparent.static_eval
+        });
         let mut can_fp: bool = false;
         //reverse futility pruning
-        if !pv_node && !in_check && !root{
-            let stand_pat: i32 = pesto_evaluate_from_scratch(board);
-            if stand_pat - 90 * depth > beta && depth < 8{
+        if !pv_node && !in_check && !root && excluded.is_none() {
+            if stand_pat - rfp_margin() * (depth - improving as i32) > beta && depth < rfp_max_depth() {
+                stat!(self.stats.rfp_prunes);
                 return stand_pat;
             }
+            //razoring: far below alpha at low depth, only captures could save the node
+            if depth <= razor_max_depth() && stand_pat + razor_margin() * depth < alpha {
+                let score: i32 = self.quiesce(board, alpha, alpha + 1, ply, 0, start_time, time_limit);
+                if score <= alpha {
+                    stat!(self.stats.razor_prunes);
+                    return score;
+                }
+            }
             //null move pruning
-            if stand_pat >= beta && depth > 3 && !in_check && can_null{
-                let nulled_board: Board = board.clone().null_move().unwrap();
-                let score: i32 = -self.pvs(&nulled_board, depth - 3, -new_beta, -new_beta + 1, ply + 1, start_time, time_limit, false);
-                if score >= beta {
-                    return beta;
+            if stand_pat >= beta && depth > nmp_min_depth() && !in_check && can_null {
+                stat!(self.stats.null_move_tries);
+                if let Some(nulled_board) = board.null_move() {
+                    self.stack[ply as usize].piece_to = None;
+                    self.stack[ply as usize + 1].cut_node = !cut_node;
+                    let score: i32 = -self.pvs(&nulled_board, depth - nmp_reduction(), -new_beta, -new_beta + 1, ply + 1, start_time, time_limit, false);
+                    if score >= beta {
+                        stat!(self.stats.null_move_cutoffs);
+                        return beta;
+                    }
+                }
+            }
+
+            //probcut: a good capture that beats beta by a margin in a shallower search almost
+            //certainly beats beta at full depth, unless the TT already says it does not
+            let probcut_beta: i32 = beta + probcut_margin();
+            if depth >= probcut_min_depth() && beta.abs() < MATE_VALUE - 1000
+                && !(tt_hit && entry.depth > depth - probcut_reduction() && entry.score < probcut_beta)
+            {
+                if let Some(score) = self.probcut(board, depth, probcut_beta, stand_pat, raw_eval, ply, start_time, time_limit, cut_node) {
+                    return score;
                 }
             }
 
             // futile pruning
-            can_fp = (stand_pat + 160 * depth) < alpha && depth < 5;
+            can_fp = (stand_pat + futility_margin() * depth + if improving { futility_improving() } else { 0 }) < alpha && depth < futility_max_depth();
         }
 
-        //generate all moves and store them in a vector
-        let mut moves = ArrayVec::<[Move; 256]>::new();
-        board.generate_moves(|p: PieceMoves| {
-            for m in p {
-                moves.push(m);
+        //singular extension: if every other move fails well below the TT score,
+        //the TT move is the only good one and gets searched deeper
+        let mut tt_extension: i32 = 0;
+        if !root && excluded.is_none() && depth >= se_min_depth() && tt_hit
+            && matches!(entry.node_type, NodeType::LowerBound | NodeType::Exact)
+            && entry.depth >= depth - se_tt_depth_margin() && entry.score.abs() < MATE_VALUE - 1000
+            && board.is_legal(tt_move)
+        {
+            let singular_beta: i32 = entry.score - se_beta_scale() * depth;
+            self.stack[ply as usize].excluded = Some(tt_move);
+            let singular_score: i32 = self.pvs(board, (depth - 1) / 2, singular_beta - 1, singular_beta, ply, start_time, time_limit, can_null);
+            self.stack[ply as usize].excluded = None;
+            if singular_score < singular_beta {
+                stat!(self.stats.singular_extensions);
+                tt_extension = 1;
+            } else if singular_beta >= beta {
+                //multi-cut: another move also beats beta, so this node fails high anyway
+                stat!(self.stats.multi_cuts);
+                return singular_beta;
+            } else if entry.score >= beta {
+                //the TT move is not the only move that holds, spend less on it
+                tt_extension = -1;
             }
-            false
-        });
-        let mut scores: Vec<i32> = self.score_moves(board, &moves, tt_move, ply);
-        self.sort_moves(&mut moves, &mut scores);
+        }
+
+        let previous: [Option<PieceTo>; 2] = self.previous_moves(ply);
+        let killers: [Move; 2] = self.stack[ply as usize].killers;
+        let counter_move: Option<Move> = self.history.counter_move(previous[0]);
+        let side: Color = board.side_to_move();
+        let mut picker: MovePicker = MovePicker::new(tt_move, killers, counter_move);
+        //late move pruning: at low depth, quiets this late in the ordering rarely matter
+        let lmp_limit: usize = if !pv_node && !in_check && !root && depth <= lmp_max_depth() {
+            ((lmp_base() + depth * depth) / if improving { 1 } else { 2 }) as usize
+        } else {
+            usize::MAX
+        };
+        let mut move_count: usize = 0; //moves picked so far, pruned ones included
+        let mut searched: ArrayVec<[Move; 256]> = ArrayVec::new();
         let mut score: i32;
         let mut node_best_move: Move = tt_move;
 
         let mut new_board = board.clone();
-        for (i, m) in moves.iter().enumerate() {
-            let is_capture: bool = self.move_is_capture(board, m);
-            if can_fp && i > 4 && !is_capture {
+        stat!(self.stats.expanded_nodes);
+        while let Some(m) = picker.next(board, &self.history, previous) {
+            if (root && self.root_excluded.contains(&m)) || excluded == Some(m) {
+                continue;
+            }
+            let i: usize = move_count;
+            move_count += 1;
+            let capture: bool = is_capture(board, &m);
+            if can_fp && i > 4 && !capture {
+                stat!(self.stats.futility_prunes);
                 continue;
             }
-            new_board.play(*m);
+            if i >= lmp_limit && !capture && m.promotion.is_none() {
+                stat!(self.stats.lmp_prunes);
+                continue;
+            }
+            searched.push(m);
+            stat!(self.stats.moves_searched);
+            self.stack[ply as usize].piece_to = Some(PieceTo::new(board, m));
+            new_board.play(m);
             self.threefold_repetition.push(new_board.hash());
             //extension on promotion to queen
             let mut mv_extension: i32 = 0;
-            if m.promotion.is_some() {
-                if m.promotion.unwrap() == Piece::Queen {
-                    mv_extension += 1;
-                }
+            if m.promotion == Some(Piece::Queen) {
+                mv_extension += 1;
+            }
+            if m == tt_move {
+                mv_extension += tt_extension;
             }
 
             let search_depth: i32 = depth + depth_modifier + mv_extension - 1;
-            //lmr
-            let mut lmr_depth: i32 = search_depth;
-            if i > 7 && depth > 2 {
-                lmr_depth -= 1;
-                if !is_capture{
-                    lmr_depth -= 1;
+            //late move reductions
+            let mut reduction: i32 = 0;
+            if depth >= lmr_min_depth() && i >= if pv_node { 3 } else { 1 } {
+                reduction = self.lmr_table[(depth as usize).min(63)][i.min(63)];
+                reduction += if pv_node { -lmr_pv() } else { 0 } + if cut_node { lmr_cut_node() } else { 0 };
+                reduction += if improving { 0 } else { lmr_not_improving() };
+                if !new_board.checkers().is_empty() {
+                    reduction -= lmr_check();
+                }
+                if capture {
+                    reduction -= lmr_capture();
+                    reduction -= self.history.capture_score(PieceTo::new(board, m), self.captured_piece(board, m)) * 1024 / lmr_history_divisor();
+                } else {
+                    if killers.contains(&m) || counter_move == Some(m) {
+                        reduction -= lmr_refutation();
+                    }
+                    reduction -= self.history.quiet_score(side, m, PieceTo::new(board, m), previous) * 1024 / lmr_history_divisor();
                 }
             }
+            //never reduce into qsearch or extend
+            let lmr_depth: i32 = (search_depth - reduction / 1024).clamp(search_depth.min(1), search_depth);
             if i == 0 { //principal variation
+                self.stack[ply as usize + 1].cut_node = !pv_node && !cut_node;
                 score = -self.pvs(&new_board, search_depth, -new_beta, -new_alpha, ply + 1, start_time, time_limit, can_null);
             }
             else {
-                score = -self.pvs(&new_board, lmr_depth, -new_alpha - 1, -new_alpha, ply + 1, start_time, time_limit, can_null);
-                if new_alpha < score { 
-                    if lmr_depth < search_depth { //if it was an lmr node
-                        score = -self.pvs(&new_board, search_depth, -new_alpha - 1, -new_alpha, ply + 1, start_time, time_limit, can_null);
-                    }
-                    //full re-search
-                    if new_alpha < score {
-                        score = -self.pvs(&new_board, search_depth, -new_beta, -new_alpha, ply + 1, start_time, time_limit, can_null);
+                //a reduced null-window search expects to fail low, so the child is expected to cut
+                score = if lmr_depth < search_depth {
+                    stat!(self.stats.lmr_searches);
+                    self.stack[ply as usize + 1].cut_node = true;
+                    -self.pvs(&new_board, lmr_depth, -new_alpha - 1, -new_alpha, ply + 1, start_time, time_limit, can_null)
+                } else {
+                    new_alpha + 1 //an unreduced move goes straight to the full-depth search
+                };
+                //the reduced search failed high, verify at full depth
+                if score > new_alpha {
+                    if lmr_depth < search_depth {
+                        stat!(self.stats.lmr_researches);
                     }
+                    self.stack[ply as usize + 1].cut_node = !cut_node;
+                    score = -self.pvs(&new_board, search_depth, -new_alpha - 1, -new_alpha, ply + 1, start_time, time_limit, can_null);
+                }
+                //only a PV node has a window wider than the null window to re-search with
+                if pv_node && score > new_alpha && score < new_beta {
+                    self.stack[ply as usize + 1].cut_node = false;
+                    score = -self.pvs(&new_board, search_depth, -new_beta, -new_alpha, ply + 1, start_time, time_limit, can_null);
                 }
             }
             self.threefold_repetition.pop();
             new_board = board.clone();
             if score > best_score {
                 best_score = score;
-                node_best_move = *m;
+                node_best_move = m;
                 if (ply == 0) && (score.abs() != self.min_val.abs()) {
-                    self.root_best_move = *m;
+                    self.root_best_move = m;
                     self.root_score = score;
                 }
             }
             new_alpha = new_alpha.max(score);
             if new_alpha >= new_beta {
-                //killers and history are quiet-move heuristics
-                if !self.move_is_capture(board, m) && m.promotion.is_none() {
-                    self.killer_table[ply as usize] = *m;
-                    self.history_table[board.side_to_move() as usize][m.from as usize][m.to as usize] += depth * depth;
-                    for j in 0..i {
-                        if !self.move_is_capture(board, &moves[j]) && moves[j].promotion.is_none() {
-                            self.history_table[board.side_to_move() as usize][moves[j].from as usize][moves[j].to as usize] -= 1;
-                        }
-                    }
+                stat!(self.stats.beta_cutoffs);
+                if i == 0 {
+                    stat!(self.stats.first_move_cutoffs);
                 }
+                self.update_histories(board, &searched, ply, depth);
                 break;
             }
         }
@@ -392,59 +670,308 @@ impl AlphaBetaSearcher {
             NodeType::Exact
         };
         //idea for later: dont store in TT if score is timeout
-        if best_score.abs() != self.min_val.abs() {
-            let tt_entry: TTEntry = TTEntry {
-                hash: board.hash(),
+        //a root searched without its best moves does not have the position's real score
+        if best_score.abs() != self.min_val.abs() && (!root || self.root_excluded.is_empty()) {
+            self.transposition_table.store(TTEntry {
+                hash: tt_key,
                 depth,
                 score: best_score,
+                static_eval: raw_eval,
                 best_move: node_best_move,
                 node_type,
-            };
-            self.transposition_table[board.hash() as usize % TT_SIZE] = tt_entry;
+            });
+        }
+        //learn the eval's error from quiet non-PV results whose bound says which side of the eval the truth is on
+        if !pv_node && !in_check && excluded.is_none() && best_score.abs() < MATE_VALUE - 1000
+            && (node_type == NodeType::UpperBound || !is_capture(board, &node_best_move))
+            && !(node_type == NodeType::LowerBound && best_score <= stand_pat)
+            && !(node_type == NodeType::UpperBound && best_score >= stand_pat)
+        {
+            self.correction.update(board, best_score - stand_pat, depth);
         }
         
         best_score
     }
 
-    pub fn get_best_move(&mut self, board: &Board, time_remaining: u64) -> String {
+    /// Tries the captures whose SEE alone clears `probcut_beta` over the static eval,
+    /// first with a qsearch and then with a reduced null-window search. Returns the
+    /// score of the first one that holds above `probcut_beta`, after storing it in the TT.
+    #[allow(clippy::too_many_arguments)]
+    fn probcut(&mut self, board: &Board, depth: i32, probcut_beta: i32, stand_pat: i32, raw_eval: i32, ply: u32, start_time: Instant, time_limit: Duration, cut_node: bool) -> Option<i32> {
+        stat!(self.stats.probcut_tries);
+        let mut picker: MovePicker = MovePicker::qsearch();
+        while let Some(m) = picker.next(board, &self.history, [None, None]) {
+            if !see_ge(board, m, probcut_beta - stand_pat) {
+                continue;
+            }
+            self.stack[ply as usize].piece_to = Some(PieceTo::new(board, m));
+            let mut new_board: Board = board.clone();
+            new_board.play(m);
+            self.threefold_repetition.push(new_board.hash());
+            let mut score: i32 = -self.quiesce(&new_board, -probcut_beta, -probcut_beta + 1, ply + 1, 0, start_time, time_limit);
+            if score >= probcut_beta {
+                self.stack[ply as usize + 1].cut_node = !cut_node;
+                score = -self.pvs(&new_board, depth - probcut_reduction(), -probcut_beta, -probcut_beta + 1, ply + 1, start_time, time_limit, true);
+            }
+            self.threefold_repetition.pop();
+            if score >= probcut_beta {
+                if score.abs() == self.min_val.abs() {
+                    return None; //out of time, the score means nothing
+                }
+                stat!(self.stats.probcut_cutoffs);
+                self.transposition_table.store(TTEntry {
+                    hash: board.hash(),
+                    depth: depth - probcut_reduction() + 1,
+                    score,
+                    static_eval: raw_eval,
+                    best_move: m,
+                    node_type: NodeType::LowerBound,
+                });
+                return Some(score);
+            }
+        }
+        None
+    }
+
+    /// Rewards the cutoff move, the last of `searched`, and penalises the moves of the
+    /// same kind searched before it: quiets, or captures that did not look winning.
+    fn update_histories(&mut self, board: &Board, searched: &[Move], ply: u32, depth: i32) {
+        let (best, tried) = searched.split_last().unwrap();
+        let bonus: i32 = history_bonus(depth);
+        let side: Color = board.side_to_move();
+        if is_capture(board, best) {
+            self.history.update_capture(PieceTo::new(board, *best), self.captured_piece(board, *best), bonus);
+            for m in tried {
+                if is_capture(board, m) && !see_ge(board, *m, 0) {
+                    self.history.update_capture(PieceTo::new(board, *m), self.captured_piece(board, *m), -bonus);
+                }
+            }
+            return;
+        }
+        //killers and the quiet histories skip promotions, they are ordered as captures
+        if best.promotion.is_some() {
+            return;
+        }
+        let previous: [Option<PieceTo>; 2] = self.previous_moves(ply);
+        let killers: &mut [Move; 2] = &mut self.stack[ply as usize].killers;
+        if killers[0] != *best {
+            killers[1] = killers[0];
+            killers[0] = *best;
+        }
+        self.history.set_counter_move(previous[0], *best);
+        self.history.update_quiet(side, *best, PieceTo::new(board, *best), previous, bonus);
+        for m in tried {
+            if !is_capture(board, m) && m.promotion.is_none() {
+                self.history.update_quiet(side, *m, PieceTo::new(board, *m), previous, -bonus);
+            }
+        }
+    }
+
+    /// Returns the move to play in UCI notation, or `0000` if there are no legal moves.
+    /// A limited strength replaces the depth and node limits with its own.
+    pub fn get_best_move(&mut self, board: &Board, mut limits: SearchLimits) -> String {
+        let strength_limit: Option<StrengthLimit> = self.strength.limit();
+        if let Some(limit) = strength_limit {
+            limits.nodes = Some(limit.nodes);
+            limits.depth = Some(limit.depth);
+            limits.multipv = LIMITED_MULTIPV;
+            self.eval_noise = limit.eval_noise;
+            self.noise_seed = rand::random();
+        }
+        let result: SearchResult = self.search(board, limits);
+        self.eval_noise = 0;
+        //the line played, with its own score when a limited strength picked a weaker one
+        let picked: Option<PvLine> = strength_limit.and_then(|limit| pick_line(&result.lines, limit.temperature));
+        let searched: Option<PvLine> = board.is_legal(result.best_move).then_some(PvLine { best_move: result.best_move, score: result.score });
+        let played: Option<PvLine> = picked.or(searched).or_else(|| {
+            //should not happen since depth 1 always completes, but never forfeit over it
+            let mut fallback: Option<Move> = None;
+            board.generate_moves(|p: PieceMoves| {
+                fallback = p.into_iter().next();
+                fallback.is_some()
+            });
+            if fallback.is_some() {
+                println!("info string search returned illegal move {} at depth {}, playing first legal move", result.best_move, result.depth);
+            }
+            fallback.map(|m| PvLine { best_move: m, score: result.score })
+        });
+        match played {
+            Some(line) => {
+                if self.verbose && self.info_format == InfoFormat::Json {
+                    let multipv: usize = result.lines.iter().position(|l| l.best_move == line.best_move).map_or(1, |i| i + 1);
+                    println!("{}", self.report("bestmove", board, result.depth, multipv, &line, NodeType::Exact, result.elapsed));
+                }
+                Self::to_standard_uci(board, line.best_move)
+            },
+            None => "0000".to_string(),
+        }
+    }
+
+    /// `line` as an NDJSON object, its PV read from the TT.
+    #[allow(clippy::too_many_arguments)]
+    fn report(&self, kind: &'static str, board: &Board, depth: i32, multipv: usize, line: &PvLine, bound: NodeType, elapsed: Duration) -> String {
+        #[cfg(feature = "stats")]
+        let stats: Option<String> = Some(self.stats.json());
+        #[cfg(not(feature = "stats"))]
+        let stats: Option<String> = None;
+        Report {
+            kind,
+            board,
+            depth,
+            seldepth: self.seldepth,
+            multipv,
+            score: line.score,
+            bound,
+            nodes: self.nodes,
+            elapsed,
+            hashfull: self.transposition_table.hashfull(),
+            pv: &self.principal_variation(board, line.best_move),
+            stats,
+        }.to_json()
+    }
+
+    /// `first` followed by the best moves stored in the TT, as far as they stay
+    /// legal and do not repeat.
+    fn principal_variation(&self, board: &Board, first: Move) -> Vec<Move> {
+        let mut pv: Vec<Move> = Vec::new();
+        let mut seen: Vec<u64> = vec![board.hash()];
+        let mut board: Board = board.clone();
+        let mut m: Move = first;
+        while board.is_legal(m) && pv.len() < 64 {
+            pv.push(m);
+            board.play(m);
+            if seen.contains(&board.hash()) {
+                break;
+            }
+            seen.push(board.hash());
+            let entry: TTEntry = self.transposition_table.probe(board.hash());
+            if entry.hash != board.hash() {
+                break;
+            }
+            m = entry.best_move;
+        }
+        pv
+    }
+
+    pub fn search(&mut self, board: &Board, limits: SearchLimits) -> SearchResult {
+        self.transposition_table.allocate(); //normally done at isready, outside the clock
         let start_time: Instant = Instant::now();
-        let hard_limit: Duration = Duration::from_millis(time_remaining/10);
-        let soft_limit: Duration = Duration::from_millis(time_remaining/40);
+        //movetime is a fixed budget, a clock is split over the moves left before it is
+        //topped up, and the increment is mostly spent as it comes in
+        let (hard_limit, soft_limit): (Duration, Duration) = match (limits.movetime, limits.time_remaining) {
+            (Some(movetime), _) => (Duration::from_millis(movetime), Duration::from_millis(movetime)),
+            (None, Some(time_remaining)) => {
+                let increment: u64 = limits.increment.unwrap_or(0);
+                let moves_left: u64 = limits.moves_to_go.map_or(time_soft_divisor() as u64, |moves| (moves as u64).clamp(1, time_soft_divisor() as u64));
+                let hard: u64 = (time_remaining / time_hard_divisor() as u64 + increment).min(time_remaining / 2);
+                let soft: u64 = (time_remaining / moves_left + increment / 2).min(hard);
+                (Duration::from_millis(hard), Duration::from_millis(soft))
+            },
+            (None, None) => (Duration::MAX, Duration::MAX),
+        };
+        let max_depth: i32 = limits.depth.unwrap_or(99).min(MAX_PLY as i32 - 1);
+        let node_limit: u64 = limits.nodes.unwrap_or(u64::MAX);
         //do iterative deepening until we run out of time
         let mut current_depth: i32 = 1;
-        let final_move: String;
+        let mut iterations: Vec<IterationInfo> = Vec::new();
+        let mut lines: Vec<PvLine> = Vec::new();
         self.nodes = 0;
+        self.seldepth = 0;
+        //tuning builds may have changed the LMR parameters through setoption
+        #[cfg(feature = "tune")]
+        {
+            self.lmr_table = lmr_table();
+        }
+        #[cfg(feature = "stats")]
+        {
+            self.stats = SearchStats::default();
+        }
         self.root_best_move = Move::from_str("a1a1").unwrap();
-        //clear history table
-        // self.history_table = vec![vec![vec![0; 64]; 64]; 2];
-        self.history_table = [[[0; 64]; 64]; 2];
-        // self.killer_table = vec![Move::from_str("a1a1").unwrap(); 128];
+        self.history.clear();
 
-        let mut aspiration_window: i32 = 15;
-        let mut alpha: i32 = -99999999;
-        let mut beta: i32 = 99999999;
+        let mut aspiration_window: i32 = aspiration_delta();
+        let mut alpha: i32 = -SCORE_INFINITY;
+        let mut beta: i32 = SCORE_INFINITY;
+        let has_legal_moves: bool = board.generate_moves(|_| true);
 
-        while start_time.elapsed() < soft_limit && current_depth < 100 {
-            let score: i32 = self.pvs(board, current_depth, alpha, beta, 0, start_time, hard_limit, true);
+        //depth 1 ignores the limits so there is always a legal move to return
+        while has_legal_moves && (current_depth == 1 || (start_time.elapsed() < soft_limit && current_depth <= max_depth && self.nodes < node_limit && !self.stop.load(Ordering::Relaxed))) {
+            self.node_limit = if current_depth == 1 { u64::MAX } else { node_limit };
+            self.stoppable = current_depth > 1;
+            let time_limit: Duration = if current_depth == 1 { Duration::MAX } else { hard_limit };
+            let score: i32 = self.pvs(board, current_depth, alpha, beta, 0, start_time, time_limit, true);
             if score <= alpha || score >= beta {
+                let timed_out: bool = self.out_of_budget(start_time, time_limit);
+                if self.verbose && self.info_format == InfoFormat::Json && !timed_out {
+                    let bound: NodeType = if score >= beta { NodeType::LowerBound } else { NodeType::UpperBound };
+                    let line: PvLine = PvLine { best_move: self.root_best_move, score };
+                    println!("{}", self.report("iteration", board, current_depth, 1, &line, bound, start_time.elapsed()));
+                }
                 //fail high or low, re-search with gradual widening
-                aspiration_window *= 2;
-                alpha = score - aspiration_window;
-                beta = score + aspiration_window;
+                //mate scores need the window to open all the way, so clamp to the full window
+                aspiration_window = aspiration_window.saturating_mul(2);
+                alpha = score.saturating_sub(aspiration_window).max(-SCORE_INFINITY);
+                beta = score.saturating_add(aspiration_window).min(SCORE_INFINITY);
                 continue;
             }
-            aspiration_window = 15;
-            alpha = score - aspiration_window;
-            beta = score + aspiration_window;
-            println!("depth {} score cp {} NPS {}k", current_depth, score, (self.nodes as f32) / (start_time.elapsed().as_secs_f32() *1000.0));
+            aspiration_window = aspiration_delta();
+            alpha = (score - aspiration_window).max(-SCORE_INFINITY);
+            beta = (score + aspiration_window).min(SCORE_INFINITY);
+            //further MultiPV lines: search again without the moves already reported, with a full window
+            let mut iteration_lines: Vec<PvLine> = vec![PvLine { best_move: self.root_best_move, score }];
+            let best_line: PvLine = iteration_lines[0];
+            while iteration_lines.len() < limits.multipv {
+                self.root_excluded = iteration_lines.iter().map(|line| line.best_move).collect();
+                self.root_best_move = Move::from_str("a1a1").unwrap();
+                let line_score: i32 = self.pvs(board, current_depth, -SCORE_INFINITY, SCORE_INFINITY, 0, start_time, time_limit, true);
+                if self.out_of_budget(start_time, time_limit) || !board.is_legal(self.root_best_move) {
+                    break;
+                }
+                iteration_lines.push(PvLine { best_move: self.root_best_move, score: line_score });
+            }
+            self.root_excluded.clear();
+            self.root_best_move = best_line.best_move;
+            self.root_score = best_line.score;
+            lines = iteration_lines;
+            if self.verbose {
+                match self.info_format {
+                    InfoFormat::Uci => println!("depth {} score cp {} NPS {}k", current_depth, score, (self.nodes as f32) / (start_time.elapsed().as_secs_f32() *1000.0)),
+                    InfoFormat::Xboard => {
+                        let (pv, _) = pv_notation(board, &self.principal_variation(board, self.root_best_move));
+                        println!("{} {} {} {} {}", current_depth, xboard_score(score), start_time.elapsed().as_millis() / 10, self.nodes, pv.join(" "));
+                    },
+                    InfoFormat::Json => for (i, line) in lines.iter().enumerate() {
+                        println!("{}", self.report("iteration", board, current_depth, i + 1, line, NodeType::Exact, start_time.elapsed()));
+                    },
+                }
+            }
+            iterations.push(IterationInfo {
+                depth: current_depth,
+                best_move: self.root_best_move,
+                elapsed: start_time.elapsed(),
+            });
             current_depth += 1;
         }
-        final_move = Self::to_standard_uci(board, self.root_best_move);
-        if !board.is_legal(self.root_best_move) {
-            panic!("Illegal move {} in position {}. Searched to depth {} with root_best_move {}", final_move, board, current_depth - 1, self.root_best_move);
+        #[cfg(feature = "stats")]
+        {
+            self.stats.nodes = self.nodes;
+        }
+        if self.verbose && self.info_format == InfoFormat::Uci {
+            println!("info depth {} score cp {} NPS {}k", current_depth - 1, self.root_score, (self.nodes as f32) / (start_time.elapsed().as_secs_f32() *1000.0));
+            #[cfg(feature = "stats")]
+            println!("{}", self.stats.info_string());
+        }
+        SearchResult {
+            best_move: self.root_best_move,
+            score: self.root_score,
+            depth: current_depth - 1,
+            nodes: self.nodes,
+            elapsed: start_time.elapsed(),
+            iterations,
+            lines,
+            #[cfg(feature = "stats")]
+            stats: self.stats,
         }
-        println!("info depth {} score cp {} NPS {}k", current_depth - 1, self.root_score, (self.nodes as f32) / (start_time.elapsed().as_secs_f32() *1000.0));
-        return final_move;
     }
 }
 
@@ -461,13 +988,99 @@ mod tests {
             to: Square::D6,
             promotion: None,
         };
-        assert!(searcher.move_is_capture(&board, &ep));
+        assert!(is_capture(&board, &ep));
         assert_eq!(searcher.captured_piece_value(&board, ep), 100);
         let quiet = Move {
             from: Square::E1,
             to: Square::E2,
             promotion: None,
         };
-        assert!(!searcher.move_is_capture(&board, &quiet));
+        assert!(!is_capture(&board, &quiet));
+    }
+
+    fn quiesce_fen(searcher: &mut AlphaBetaSearcher, fen: &str) -> i32 {
+        let board: Board = Board::from_fen(fen, false).unwrap();
+        searcher.set_hash_size(1);
+        searcher.allocate_hash();
+        searcher.quiesce(&board, -SCORE_INFINITY, SCORE_INFINITY, 1, 0, Instant::now(), Duration::MAX)
+    }
+
+    #[test]
+    fn quiesce_wins_hanging_piece_and_stores_it() {
+        let mut searcher: AlphaBetaSearcher = AlphaBetaSearcher::new();
+        let fen: &str = "4k3/8/8/3q4/8/8/8/3RK3 w - - 0 1";
+        assert!(quiesce_fen(&mut searcher, fen) > 300);
+        let hash: u64 = Board::from_fen(fen, false).unwrap().hash();
+        let entry: TTEntry = searcher.transposition_table.probe(hash);
+        assert!(entry.hash == hash && entry.depth == 0);
+        assert_eq!(entry.best_move, "d1d5".parse().unwrap());
+    }
+
+    #[test]
+    fn quiesce_skips_losing_captures() {
+        //Qxd5 cxd5 loses the queen, so standing pat is best
+        let mut searcher: AlphaBetaSearcher = AlphaBetaSearcher::new();
+        let fen: &str = "4k3/8/2p5/3p4/8/8/8/3QK3 w - - 0 1";
+        let stand_pat: i32 = pesto_evaluate_from_scratch(&Board::from_fen(fen, false).unwrap());
+        assert_eq!(quiesce_fen(&mut searcher, fen), stand_pat);
+    }
+
+    #[test]
+    fn quiesce_searches_evasions_and_sees_mate() {
+        let mut searcher: AlphaBetaSearcher = AlphaBetaSearcher::new();
+        //fool's mate
+        assert_eq!(quiesce_fen(&mut searcher, "rnb1kbnr/pppp1ppp/8/4p3/6Pq/5P2/PPPPP2P/RNBQKBNR w KQkq - 1 3"), -MATE_VALUE + 1);
+        //the checking queen is loose, so the king takes it instead of standing pat a queen down
+        assert!(quiesce_fen(&mut searcher, "4k3/8/8/8/8/8/3q4/4K3 w - - 0 1").abs() < 100);
+    }
+
+    #[test]
+    fn quiesce_in_check_ignores_the_static_eval_of_a_pvs_entry() {
+        let mut searcher: AlphaBetaSearcher = AlphaBetaSearcher::new();
+        searcher.set_hash_size(1);
+        searcher.allocate_hash();
+        let board: Board = Board::from_fen("4k3/8/8/8/8/8/3q4/4K3 w - - 0 1", false).unwrap();
+        //a fail low leaves an upper bound that does not cut the qsearch window below
+        searcher.pvs(&board, 1, 1000, 1001, 1, Instant::now(), Duration::MAX, false);
+        let entry: TTEntry = searcher.transposition_table.probe(board.hash());
+        assert!(entry.hash == board.hash() && entry.node_type == NodeType::UpperBound);
+        //past the first qsearch ply it may stand pat, on the real eval rather than the stored 0
+        let score: i32 = searcher.quiesce(&board, -5000, -4999, 2, 1, Instant::now(), Duration::MAX);
+        assert_eq!(score, searcher.evaluate(&board));
+        assert!(score < -500);
+    }
+
+    #[test]
+    fn quiesce_plays_queen_promotions() {
+        let mut searcher: AlphaBetaSearcher = AlphaBetaSearcher::new();
+        assert!(quiesce_fen(&mut searcher, "8/4P3/8/8/8/8/k7/4K3 w - - 0 1") > 700);
+    }
+
+    #[test]
+    fn pvs_stops_at_the_maximum_ply() {
+        let mut searcher: AlphaBetaSearcher = AlphaBetaSearcher::new();
+        searcher.set_hash_size(1);
+        searcher.allocate_hash();
+        //in check, so the check extension alone would keep the search going
+        let board: Board = Board::from_fen("4k3/8/8/8/8/8/3q4/4K3 w - - 0 1", false).unwrap();
+        let score: i32 = searcher.pvs(&board, 1, -SCORE_INFINITY, SCORE_INFINITY, MAX_PLY as u32 - 1, Instant::now(), Duration::MAX, false);
+        assert_eq!(score, searcher.evaluate(&board));
+        assert_eq!(searcher.nodes, 1);
+    }
+
+    #[test]
+    fn changing_the_strength_limit_clears_noisy_entries() {
+        let mut searcher: AlphaBetaSearcher = AlphaBetaSearcher::new();
+        searcher.set_verbose(false);
+        searcher.set_hash_size(1);
+        let limited: StrengthSettings = StrengthSettings { limit_strength: true, elo: MIN_ELO, ..StrengthSettings::default() };
+        searcher.set_strength(limited);
+        let board: Board = Board::default();
+        searcher.get_best_move(&board, SearchLimits::default());
+        assert_eq!(searcher.transposition_table.probe(board.hash()).hash, board.hash());
+        searcher.set_strength(limited);
+        assert_eq!(searcher.transposition_table.probe(board.hash()).hash, board.hash());
+        searcher.set_strength(StrengthSettings::default());
+        assert_eq!(searcher.transposition_table.probe(board.hash()).hash, 0);
     }
 }
@@ -0,0 +1,109 @@
+use cozy_chess::*;
+
+/// Piece values for exchanges, same scale as the search's material values.
+const SEE_VALUES: [i32; Piece::NUM] = [100, 320, 330, 500, 900, 20000];
+
+fn see_value(piece: Piece) -> i32 {
+    SEE_VALUES[piece as usize]
+}
+
+/// Every piece of either color attacking `square` given the occupancy `occupied`.
+fn attackers_to(board: &Board, square: Square, occupied: BitBoard) -> BitBoard {
+    let diagonal: BitBoard = board.pieces(Piece::Bishop) | board.pieces(Piece::Queen);
+    let orthogonal: BitBoard = board.pieces(Piece::Rook) | board.pieces(Piece::Queen);
+    (get_pawn_attacks(square, Color::White) & board.colored_pieces(Color::Black, Piece::Pawn))
+        | (get_pawn_attacks(square, Color::Black) & board.colored_pieces(Color::White, Piece::Pawn))
+        | (get_knight_moves(square) & board.pieces(Piece::Knight))
+        | (get_bishop_moves(square, occupied) & diagonal)
+        | (get_rook_moves(square, occupied) & orthogonal)
+        | (get_king_moves(square) & board.pieces(Piece::King))
+}
+
+/// Static exchange evaluation: whether the exchange started by `m` on its destination
+/// square wins at least `threshold` cp when both sides recapture with their least
+/// valuable attacker and may stop at any point. Pins are ignored; castling and
+/// promotions are treated as exchanging nothing.
+pub fn see_ge(board: &Board, m: Move, threshold: i32) -> bool {
+    let stm: Color = board.side_to_move();
+    if m.promotion.is_some() || board.color_on(m.to) == Some(stm) {
+        return threshold <= 0;
+    }
+    let mover: Piece = board.piece_on(m.from).unwrap();
+    let mut occupied: BitBoard = board.occupied() ^ m.from.bitboard() ^ m.to.bitboard();
+    let mut swap: i32 = match board.piece_on(m.to) {
+        Some(victim) => see_value(victim),
+        None if mover == Piece::Pawn && m.from.file() != m.to.file() => {
+            //en passant: the captured pawn is beside the destination, not on it
+            occupied ^= Square::new(m.to.file(), m.from.rank()).bitboard();
+            see_value(Piece::Pawn)
+        },
+        None => 0,
+    } - threshold;
+    if swap < 0 {
+        return false;
+    }
+    //the opponent recaptures the moved piece right away; if that still clears the threshold we are done
+    swap = see_value(mover) - swap;
+    if swap <= 0 {
+        return true;
+    }
+    let diagonal: BitBoard = board.pieces(Piece::Bishop) | board.pieces(Piece::Queen);
+    let orthogonal: BitBoard = board.pieces(Piece::Rook) | board.pieces(Piece::Queen);
+    let mut attackers: BitBoard = attackers_to(board, m.to, occupied);
+    let mut side: Color = stm;
+    let mut result: bool = true;
+    loop {
+        side = !side;
+        attackers &= occupied;
+        let side_attackers: BitBoard = attackers & board.colors(side);
+        if side_attackers.is_empty() {
+            break;
+        }
+        result = !result;
+        let piece: Piece = Piece::ALL.into_iter().find(|&p| !(side_attackers & board.pieces(p)).is_empty()).unwrap();
+        if piece == Piece::King {
+            //the king may only recapture if the other side has no attackers left
+            if !(attackers & !board.colors(side)).is_empty() {
+                result = !result;
+            }
+            break;
+        }
+        swap = see_value(piece) - swap;
+        if swap < result as i32 {
+            break;
+        }
+        occupied ^= (side_attackers & board.pieces(piece)).next_square().unwrap().bitboard();
+        //sliders behind the piece that just captured join in
+        if matches!(piece, Piece::Pawn | Piece::Bishop | Piece::Queen) {
+            attackers |= get_bishop_moves(m.to, occupied) & diagonal;
+        }
+        if matches!(piece, Piece::Rook | Piece::Queen) {
+            attackers |= get_rook_moves(m.to, occupied) & orthogonal;
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn see(fen: &str, m: &str, threshold: i32) -> bool {
+        see_ge(&Board::from_fen(fen, false).unwrap(), m.parse().unwrap(), threshold)
+    }
+
+    #[test]
+    fn exchanges_are_resolved_with_least_valuable_attackers() {
+        //undefended pawn
+        assert!(see("1k1r4/1pp4p/p7/4p3/8/P5P1/1PP4P/2K1R3 w - - 0 1", "e1e5", 100));
+        assert!(!see("1k1r4/1pp4p/p7/4p3/8/P5P1/1PP4P/2K1R3 w - - 0 1", "e1e5", 101));
+        //NxP defended by a pawn loses the knight for a pawn
+        assert!(see("4k3/8/3p4/4p3/8/5N2/8/4K3 w - - 0 1", "f3e5", -220));
+        assert!(!see("4k3/8/3p4/4p3/8/5N2/8/4K3 w - - 0 1", "f3e5", -219));
+        //x-ray: the queen behind the rook recaptures after the rook is taken
+        assert!(see("6k1/3r4/8/8/3n4/8/3R4/3Q2K1 w - - 0 1", "d2d4", 320));
+        assert!(!see("6k1/3r4/8/8/3n4/8/3R4/3Q2K1 w - - 0 1", "d2d4", 321));
+        //en passant wins a pawn
+        assert!(see("4k3/8/8/3pP3/8/8/8/4K3 w - d6 0 1", "e5d6", 100));
+    }
+}
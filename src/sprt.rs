@@ -0,0 +1,125 @@
+/// Game-pair statistics for A/B testing, using the same model as fastchess
+/// `-sprt ... model=logistic`: each opening is played twice with colors
+/// reversed and the pair score (0, 0.5, 1, 1.5 or 2 points) is one sample.
+#[derive(Clone, Copy, Default)]
+pub struct Pentanomial {
+    pub counts: [u64; 5], //indexed by the pair score in half points
+}
+
+#[derive(Clone, Copy)]
+pub struct SprtConfig {
+    pub elo0: f64,
+    pub elo1: f64,
+    pub alpha: f64,
+    pub beta: f64,
+}
+
+impl Default for SprtConfig {
+    fn default() -> Self {
+        //same bounds as scripts/sprt.sh and cutechess_commands.txt
+        SprtConfig { elo0: 0.0, elo1: 5.0, alpha: 0.05, beta: 0.05 }
+    }
+}
+
+impl SprtConfig {
+    /// (lower, upper) LLR bounds: H0 is accepted below, H1 above.
+    pub fn bounds(&self) -> (f64, f64) {
+        ((self.beta / (1.0 - self.alpha)).ln(), ((1.0 - self.beta) / self.alpha).ln())
+    }
+}
+
+impl Pentanomial {
+    pub fn add_pair(&mut self, half_points: usize) {
+        self.counts[half_points] += 1;
+    }
+
+    pub fn pairs(&self) -> u64 {
+        self.counts.iter().sum()
+    }
+
+    /// Mean and variance of the per-pair score, scaled to [0, 1].
+    fn mean_variance(&self) -> (f64, f64) {
+        let n: f64 = self.pairs() as f64;
+        if n == 0.0 {
+            return (0.5, 0.0);
+        }
+        let mean: f64 = self.counts.iter().enumerate().map(|(i, &c)| c as f64 * i as f64 / 4.0).sum::<f64>() / n;
+        let variance: f64 = self.counts.iter().enumerate().map(|(i, &c)| c as f64 * (i as f64 / 4.0 - mean).powi(2)).sum::<f64>() / n;
+        (mean, variance)
+    }
+
+    /// Elo difference and its 95% error margin.
+    pub fn elo(&self) -> (f64, f64) {
+        let (mean, variance) = self.mean_variance();
+        let n: f64 = self.pairs().max(1) as f64;
+        let margin: f64 = 1.959964 * (variance / n).sqrt();
+        let upper: f64 = score_to_elo(mean + margin);
+        let lower: f64 = score_to_elo(mean - margin);
+        (score_to_elo(mean), (upper - lower) / 2.0)
+    }
+
+    /// Likelihood of superiority, in [0, 1].
+    pub fn los(&self) -> f64 {
+        let (mean, variance) = self.mean_variance();
+        let n: f64 = self.pairs() as f64;
+        if variance == 0.0 {
+            return if mean > 0.5 { 1.0 } else if mean < 0.5 { 0.0 } else { 0.5 };
+        }
+        0.5 * (1.0 + erf((mean - 0.5) / (2.0 * variance / n).sqrt()))
+    }
+
+    /// Log-likelihood ratio of H1 (elo1) against H0 (elo0), normal approximation of the GSPRT.
+    pub fn llr(&self, config: &SprtConfig) -> f64 {
+        let (mean, variance) = self.mean_variance();
+        if variance == 0.0 {
+            return 0.0;
+        }
+        let s0: f64 = elo_to_score(config.elo0);
+        let s1: f64 = elo_to_score(config.elo1);
+        self.pairs() as f64 * (s1 - s0) * (2.0 * mean - s0 - s1) / (2.0 * variance)
+    }
+}
+
+pub fn elo_to_score(elo: f64) -> f64 {
+    1.0 / (1.0 + 10f64.powf(-elo / 400.0))
+}
+
+pub fn score_to_elo(score: f64) -> f64 {
+    let score: f64 = score.clamp(1e-6, 1.0 - 1e-6);
+    -400.0 * (1.0 / score - 1.0).log10()
+}
+
+fn erf(x: f64) -> f64 {
+    //Abramowitz and Stegun 7.1.26, accurate to ~1e-7
+    let t: f64 = 1.0 / (1.0 + 0.3275911 * x.abs());
+    let poly: f64 = t * (0.254829592 + t * (-0.284496736 + t * (1.421413741 + t * (-1.453152027 + t * 1.061405429))));
+    let y: f64 = 1.0 - poly * (-x * x).exp();
+    if x < 0.0 { -y } else { y }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn default_bounds_match_fastchess() {
+        let (lower, upper) = SprtConfig::default().bounds();
+        assert!((lower + 2.944).abs() < 0.001);
+        assert!((upper - 2.944).abs() < 0.001);
+    }
+
+    #[test]
+    fn llr_follows_the_score() {
+        let config: SprtConfig = SprtConfig::default();
+        let mut even: Pentanomial = Pentanomial { counts: [10, 50, 200, 50, 10] };
+        assert!(even.elo().0.abs() < 1e-9);
+        assert!(even.llr(&config) < 0.0);
+        assert!((even.los() - 0.5).abs() < 1e-9);
+        for _ in 0..100 {
+            even.add_pair(3);
+        }
+        assert!(even.elo().0 > 0.0);
+        assert!(even.llr(&config) > 0.0);
+        assert!(even.los() > 0.99);
+    }
+}
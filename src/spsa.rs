@@ -0,0 +1,120 @@
+use crate::tournament::{MatchConfig, PairPlayer};
+use crate::tunables::{Tunable, TUNABLES};
+use rand::Rng;
+
+//the gain schedule fishtest and OpenBench use: a_k = a / (A + k)^ALPHA, c_k = c / k^GAMMA
+const ALPHA: f64 = 0.602;
+const GAMMA: f64 = 0.101;
+//A as a share of the iterations
+const STABILITY: f64 = 0.1;
+//a_k / c_k^2 at the last iteration, the learning rate of `tunables::openbench_config`
+const LEARNING_RATE: f64 = 0.002;
+
+struct Parameter {
+    tunable: &'static Tunable,
+    value: f64,
+    a: f64,
+    c: f64,
+}
+
+/// SPSA over every tunable parameter, with the perturbation ending at each parameter's
+/// `step` after the planned number of iterations.
+struct Spsa {
+    parameters: Vec<Parameter>,
+    stability: f64,
+}
+
+impl Spsa {
+    fn new(iterations: usize) -> Spsa {
+        let iterations: f64 = iterations.max(1) as f64;
+        let stability: f64 = STABILITY * iterations;
+        let parameters: Vec<Parameter> = TUNABLES.iter().map(|tunable| {
+            let c_end: f64 = tunable.step as f64;
+            Parameter {
+                tunable,
+                value: tunable.default as f64,
+                a: LEARNING_RATE * c_end * c_end * (stability + iterations).powf(ALPHA),
+                c: c_end * iterations.powf(GAMMA),
+            }
+        }).collect();
+        Spsa { parameters, stability }
+    }
+
+    fn c_k(&self, parameter: &Parameter, k: usize) -> f64 {
+        parameter.c / (k as f64).powf(GAMMA)
+    }
+
+    /// The values of iteration `k` (from 1) moved by `+c_k` and by `-c_k` in the `flips` directions.
+    fn perturb(&self, k: usize, flips: &[f64]) -> [Vec<i32>; 2] {
+        [1.0, -1.0].map(|sign| {
+            self.parameters.iter().zip(flips).map(|(p, flip)| {
+                let value: f64 = p.value + sign * flip * self.c_k(p, k);
+                (value.round() as i32).clamp(p.tunable.min, p.tunable.max)
+            }).collect()
+        })
+    }
+
+    /// Moves every value along its flip by how much better the `+` side did, in wins minus losses.
+    fn update(&mut self, k: usize, flips: &[f64], result: f64) {
+        for (i, flip) in flips.iter().enumerate() {
+            let c_k: f64 = self.c_k(&self.parameters[i], k);
+            let p: &mut Parameter = &mut self.parameters[i];
+            let a_k: f64 = p.a / (self.stability + k as f64).powf(ALPHA);
+            p.value = (p.value + a_k / c_k * result * flip).clamp(p.tunable.min as f64, p.tunable.max as f64);
+        }
+    }
+
+    fn print_values(&self) {
+        for p in &self.parameters {
+            println!("{}, {}", p.tunable.name, p.value.round() as i32);
+        }
+    }
+}
+
+/// Tunes the `tunables` parameters by SPSA, playing one game pair per iteration
+/// between two copies of `config.engines[0]`, which must be a `--features tune`
+/// build so it takes the parameters as UCI options. `config.rounds` is the number
+/// of iterations.
+pub fn run_spsa(config: MatchConfig) -> std::io::Result<()> {
+    let iterations: usize = config.rounds;
+    let interval: usize = config.rating_interval.max(1);
+    println!("SPSA of {} parameters: {} iterations at tc {}", TUNABLES.len(), iterations, config.time_control);
+    let mut player: PairPlayer = PairPlayer::new(config)?;
+    let mut spsa: Spsa = Spsa::new(iterations);
+    let mut rng = rand::thread_rng();
+    for k in 1..=iterations {
+        let flips: Vec<f64> = TUNABLES.iter().map(|_| if rng.gen() { 1.0 } else { -1.0 }).collect();
+        let [plus, minus] = spsa.perturb(k, &flips);
+        let options = |values: &[i32]| -> Vec<(&str, i32)> { TUNABLES.iter().map(|t| t.name).zip(values.iter().copied()).collect() };
+        let half_points: usize = player.play_pair([&options(&plus), &options(&minus)])?;
+        //in a pair, wins minus losses is the half points above an even score
+        spsa.update(k, &flips, half_points as f64 - 2.0);
+        if k % interval == 0 || k == iterations {
+            println!("Iteration {}/{}:", k, iterations);
+            spsa.print_values();
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn perturbations_shrink_to_the_step_and_results_move_the_values() {
+        let iterations: usize = 100;
+        let mut spsa: Spsa = Spsa::new(iterations);
+        let flips: Vec<f64> = TUNABLES.iter().enumerate().map(|(i, _)| if i % 2 == 0 { 1.0 } else { -1.0 }).collect();
+        let [plus, minus] = spsa.perturb(iterations, &flips);
+        let rfp: &Tunable = &TUNABLES[0];
+        assert_eq!((plus[0], minus[0]), (rfp.default + rfp.step, rfp.default - rfp.step));
+
+        //the + side winning both games pulls each value towards its + perturbation
+        spsa.update(1, &flips, 2.0);
+        for (p, flip) in spsa.parameters.iter().zip(&flips) {
+            assert!((p.value - p.tunable.default as f64) * flip > 0.0, "{}", p.tunable.name);
+            assert!(p.value >= p.tunable.min as f64 && p.value <= p.tunable.max as f64);
+        }
+    }
+}
@@ -0,0 +1,100 @@
+/// Search tree counters, only compiled with `--features stats`.
+/// Arrays indexed by node type use `[non-PV, PV]`.
+#[derive(Clone, Copy, Default)]
+pub struct SearchStats {
+    pub nodes: u64,
+    pub qnodes: u64,
+    pub tt_probes: [u64; 2],
+    pub tt_hits: [u64; 2],
+    pub tt_cutoffs: u64, //only non-PV nodes take TT cutoffs
+    pub expanded_nodes: u64, //nodes that reached the move loop
+    pub moves_searched: u64,
+    pub beta_cutoffs: u64,
+    pub first_move_cutoffs: u64,
+    pub null_move_tries: u64,
+    pub null_move_cutoffs: u64,
+    pub rfp_prunes: u64,
+    pub futility_prunes: u64,
+    pub razor_prunes: u64,
+    pub probcut_tries: u64,
+    pub probcut_cutoffs: u64,
+    pub lmp_prunes: u64,
+    pub lmr_searches: u64,
+    pub lmr_researches: u64,
+    pub singular_extensions: u64,
+    pub multi_cuts: u64,
+}
+
+fn percent(part: u64, total: u64) -> f64 {
+    part as f64 * 100.0 / total.max(1) as f64
+}
+
+impl SearchStats {
+    pub fn merge(&mut self, other: &SearchStats) {
+        self.nodes += other.nodes;
+        self.qnodes += other.qnodes;
+        for i in 0..2 {
+            self.tt_probes[i] += other.tt_probes[i];
+            self.tt_hits[i] += other.tt_hits[i];
+        }
+        self.tt_cutoffs += other.tt_cutoffs;
+        self.expanded_nodes += other.expanded_nodes;
+        self.moves_searched += other.moves_searched;
+        self.beta_cutoffs += other.beta_cutoffs;
+        self.first_move_cutoffs += other.first_move_cutoffs;
+        self.null_move_tries += other.null_move_tries;
+        self.null_move_cutoffs += other.null_move_cutoffs;
+        self.rfp_prunes += other.rfp_prunes;
+        self.futility_prunes += other.futility_prunes;
+        self.razor_prunes += other.razor_prunes;
+        self.probcut_tries += other.probcut_tries;
+        self.probcut_cutoffs += other.probcut_cutoffs;
+        self.lmp_prunes += other.lmp_prunes;
+        self.lmr_searches += other.lmr_searches;
+        self.lmr_researches += other.lmr_researches;
+        self.singular_extensions += other.singular_extensions;
+        self.multi_cuts += other.multi_cuts;
+    }
+
+    /// The raw counters as a JSON object, for the NDJSON output.
+    pub fn json(&self) -> String {
+        format!(
+            "{{\"nodes\":{},\"qnodes\":{},\"tt_probes\":[{},{}],\"tt_hits\":[{},{}],\"tt_cutoffs\":{},\"expanded_nodes\":{},\"moves_searched\":{},\
+             \"beta_cutoffs\":{},\"first_move_cutoffs\":{},\"null_move_tries\":{},\"null_move_cutoffs\":{},\"rfp_prunes\":{},\"futility_prunes\":{},\
+             \"razor_prunes\":{},\"probcut_tries\":{},\"probcut_cutoffs\":{},\"lmp_prunes\":{},\"lmr_searches\":{},\"lmr_researches\":{},\
+             \"singular_extensions\":{},\"multi_cuts\":{}}}",
+            self.nodes, self.qnodes, self.tt_probes[0], self.tt_probes[1], self.tt_hits[0], self.tt_hits[1], self.tt_cutoffs,
+            self.expanded_nodes, self.moves_searched, self.beta_cutoffs, self.first_move_cutoffs, self.null_move_tries,
+            self.null_move_cutoffs, self.rfp_prunes, self.futility_prunes, self.razor_prunes, self.probcut_tries,
+            self.probcut_cutoffs, self.lmp_prunes, self.lmr_searches, self.lmr_researches, self.singular_extensions, self.multi_cuts,
+        )
+    }
+
+    /// Single `info string` line so GUIs and match runners ignore it.
+    pub fn info_string(&self) -> String {
+        format!(
+            "info string stats nodes {} qnodes {:.1}% tt_hit pv {:.1}% nonpv {:.1}% tt_cut nonpv {:.1}% \
+             first_move_cut {:.1}% nmp {}/{} ({:.1}%) rfp {} futility {} razor {} probcut {}/{} lmp {} lmr {} research {:.1}% singular {} multicut {} branching {:.2}",
+            self.nodes,
+            percent(self.qnodes, self.nodes),
+            percent(self.tt_hits[1], self.tt_probes[1]),
+            percent(self.tt_hits[0], self.tt_probes[0]),
+            percent(self.tt_cutoffs, self.tt_probes[0]),
+            percent(self.first_move_cutoffs, self.beta_cutoffs),
+            self.null_move_cutoffs,
+            self.null_move_tries,
+            percent(self.null_move_cutoffs, self.null_move_tries),
+            self.rfp_prunes,
+            self.futility_prunes,
+            self.razor_prunes,
+            self.probcut_cutoffs,
+            self.probcut_tries,
+            self.lmp_prunes,
+            self.lmr_searches,
+            percent(self.lmr_researches, self.lmr_searches),
+            self.singular_extensions,
+            self.multi_cuts,
+            self.moves_searched as f64 / self.expanded_nodes.max(1) as f64,
+        )
+    }
+}
@@ -0,0 +1,129 @@
+use rand::Rng;
+use crate::search::PvLine;
+
+pub const MIN_ELO: i32 = 1000;
+pub const MAX_ELO: i32 = 2600;
+pub const MAX_SKILL_LEVEL: i32 = 20;
+/// Root moves considered when a limited engine picks a sub-optimal move.
+pub const LIMITED_MULTIPV: usize = 4;
+
+/// How a limited engine searches: a node and depth budget, noise added to the
+/// static eval (in cp) and a softmax temperature (in cp) for choosing among MultiPV lines.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct StrengthLimit {
+    pub nodes: u64,
+    pub depth: i32,
+    pub eval_noise: i32,
+    pub temperature: f64,
+}
+
+/// Elo anchors for fixed-node play, interpolated in between. Adjacent anchors are
+/// 400 Elo apart in direct matches of this engine against itself, the weakest
+/// pinned at 1000; `scripts/calibrate_elo.sh` replays the matches and
+/// `calibration/` holds their results. Re-run it when the engine's strength changes.
+const ANCHORS: [(i32, StrengthLimit); 5] = [
+    (1000, StrengthLimit { nodes: 200, depth: 2, eval_noise: 120, temperature: 150.0 }),
+    (1400, StrengthLimit { nodes: 670, depth: 3, eval_noise: 90, temperature: 112.0 }),
+    (1800, StrengthLimit { nodes: 2500, depth: 5, eval_noise: 57, temperature: 78.0 }),
+    (2200, StrengthLimit { nodes: 7000, depth: 6, eval_noise: 38, temperature: 55.0 }),
+    (2600, StrengthLimit { nodes: 21300, depth: 7, eval_noise: 23, temperature: 34.0 }),
+];
+
+/// The `UCI_LimitStrength`, `UCI_Elo` and `Skill Level` options.
+#[derive(Clone, Copy)]
+pub struct StrengthSettings {
+    pub limit_strength: bool,
+    pub elo: i32,
+    pub skill_level: i32,
+}
+
+impl Default for StrengthSettings {
+    fn default() -> Self {
+        StrengthSettings { limit_strength: false, elo: MAX_ELO, skill_level: MAX_SKILL_LEVEL }
+    }
+}
+
+impl StrengthSettings {
+    /// `UCI_Elo` takes precedence when `UCI_LimitStrength` is set, otherwise
+    /// `Skill Level` spreads linearly over the Elo range. Full strength is `None`.
+    pub fn target_elo(&self) -> Option<i32> {
+        if self.limit_strength {
+            return Some(self.elo.clamp(MIN_ELO, MAX_ELO));
+        }
+        if self.skill_level < MAX_SKILL_LEVEL {
+            return Some(MIN_ELO + self.skill_level.max(0) * (MAX_ELO - MIN_ELO) / MAX_SKILL_LEVEL);
+        }
+        None
+    }
+
+    pub fn limit(&self) -> Option<StrengthLimit> {
+        let elo: i32 = self.target_elo()?;
+        let upper: usize = ANCHORS.iter().position(|(anchor, _)| *anchor >= elo).unwrap_or(ANCHORS.len() - 1);
+        if upper == 0 {
+            return Some(ANCHORS[0].1);
+        }
+        let (low_elo, low) = ANCHORS[upper - 1];
+        let (high_elo, high) = ANCHORS[upper];
+        let t: f64 = (elo - low_elo) as f64 / (high_elo - low_elo) as f64;
+        //node budgets grow geometrically with strength, everything else linearly
+        let lerp = |a: f64, b: f64| a + (b - a) * t;
+        Some(StrengthLimit {
+            nodes: ((low.nodes as f64).ln() + ((high.nodes as f64).ln() - (low.nodes as f64).ln()) * t).exp() as u64,
+            depth: lerp(low.depth as f64, high.depth as f64).round() as i32,
+            eval_noise: lerp(low.eval_noise as f64, high.eval_noise as f64).round() as i32,
+            temperature: lerp(low.temperature, high.temperature),
+        })
+    }
+}
+
+/// Softmax choice among MultiPV lines: a line `temperature` cp worse than the best
+/// is e times less likely. Returns `None` without lines.
+pub fn pick_line(lines: &[PvLine], temperature: f64) -> Option<PvLine> {
+    let best: i32 = lines.iter().map(|line| line.score).max()?;
+    if temperature <= 0.0 {
+        return lines.iter().find(|line| line.score == best).copied();
+    }
+    let weights: Vec<f64> = lines.iter().map(|line| ((line.score - best) as f64 / temperature).exp()).collect();
+    let mut choice: f64 = rand::thread_rng().gen::<f64>() * weights.iter().sum::<f64>();
+    for (line, weight) in lines.iter().zip(weights) {
+        if choice < weight {
+            return Some(*line);
+        }
+        choice -= weight;
+    }
+    lines.last().copied()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn limits_grow_with_elo() {
+        let mut settings: StrengthSettings = StrengthSettings::default();
+        assert_eq!(settings.limit(), None);
+        settings.limit_strength = true;
+        let mut previous: Option<StrengthLimit> = None;
+        for elo in (MIN_ELO..=MAX_ELO).step_by(100) {
+            settings.elo = elo;
+            let limit: StrengthLimit = settings.limit().unwrap();
+            if let Some(previous) = previous {
+                assert!(limit.nodes >= previous.nodes && limit.depth >= previous.depth);
+                assert!(limit.eval_noise <= previous.eval_noise && limit.temperature <= previous.temperature);
+            }
+            previous = Some(limit);
+        }
+        let skill: StrengthSettings = StrengthSettings { skill_level: 0, ..StrengthSettings::default() };
+        assert_eq!(skill.target_elo(), Some(MIN_ELO));
+    }
+
+    #[test]
+    fn zero_temperature_picks_the_best_line() {
+        let lines: [PvLine; 2] = [
+            PvLine { best_move: "e2e4".parse().unwrap(), score: 10 },
+            PvLine { best_move: "d2d4".parse().unwrap(), score: 30 },
+        ];
+        assert_eq!(pick_line(&lines, 0.0).map(|line| (line.best_move, line.score)), Some(("d2d4".parse().unwrap(), 30)));
+        assert_eq!(pick_line(&lines[..1], 50.0).map(|line| line.best_move), Some("e2e4".parse().unwrap()));
+    }
+}
@@ -0,0 +1,142 @@
+//! Property tests: the evaluation and search must not care which color is which.
+use cozy_chess::*;
+use crate::evaluation::*;
+use crate::search::*;
+use crate::uci::flip_board as flip;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+const CORPUS: [&str; 12] = [
+    "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+    "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+    "r1bq1rk1/pp2bppp/2n1pn2/3p4/2PP4/2N1PN2/PP3PPP/R2QKB1R w KQ - 0 8",
+    "2rr3k/pp3pp1/1nnqbN1p/3pN3/2pP4/2P3Q1/PPB4P/R4RK1 w - - 0 1",
+    "r4rk1/1pp1qppp/p1np1n2/2b1p1B1/2B1P1b1/P1NP1N2/1PP1QPPP/R4RK1 w - - 0 10",
+    "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1",
+    "8/8/4k3/3p4/3P4/4K3/8/8 w - - 0 1",
+    "6k1/5ppp/8/8/8/8/5PPP/3R2K1 w - - 0 1",
+    "rnbqkb1r/pp1p1ppp/4pn2/2pP4/2P5/8/PP2PPPP/RNBQKBNR w KQkq c6 0 4",
+    "4k3/8/4K3/4P3/8/8/8/8 b - - 0 1",
+    "8/8/8/3k4/8/8/8/R3K3 w - - 0 1",
+    "4k3/3b4/8/8/3PP3/8/8/2B1K3 b - - 0 1",
+];
+
+/// The position reflected left to right. Castling rights do not survive the reflection
+/// and are dropped.
+fn mirror(board: &Board) -> Board {
+    let fen: String = board.to_string();
+    let fields: Vec<&str> = fen.split_whitespace().collect();
+    let placement: String = fields[0].split('/').map(|rank| {
+        let mut squares: Vec<char> = Vec::new();
+        for c in rank.chars() {
+            match c.to_digit(10) {
+                Some(empty) => squares.extend(std::iter::repeat_n('1', empty as usize)),
+                None => squares.push(c),
+            }
+        }
+        squares.into_iter().rev().collect::<String>()
+    }).collect::<Vec<String>>().join("/");
+    let en_passant: String = match fields[3] {
+        "-" => "-".to_string(),
+        square => square.parse::<Square>().unwrap().flip_file().to_string(),
+    };
+    let mirrored: String = format!("{} {} - {} {} {}", placement, fields[1], en_passant, fields[4], fields[5]);
+    Board::from_fen(&mirrored, false).unwrap()
+}
+
+/// The corpus plus positions reached by random legal moves from it.
+fn positions() -> Vec<Board> {
+    let mut rng: StdRng = StdRng::seed_from_u64(0x5EED);
+    let mut boards: Vec<Board> = Vec::new();
+    for fen in CORPUS {
+        let mut board: Board = Board::from_fen(fen, false).unwrap();
+        boards.push(board.clone());
+        for _ in 0..40 {
+            let mut moves: Vec<Move> = Vec::new();
+            board.generate_moves(|p| {
+                moves.extend(p);
+                false
+            });
+            if moves.is_empty() {
+                break;
+            }
+            board.play(moves[rng.gen_range(0..moves.len())]);
+            boards.push(board.clone());
+        }
+    }
+    boards
+}
+
+#[test]
+fn flip_and_mirror_round_trip() {
+    for board in positions() {
+        assert_eq!(flip(&flip(&board)), board);
+        let castling: bool = Color::ALL.iter().any(|&c| board.castle_rights(c).short.is_some() || board.castle_rights(c).long.is_some());
+        if !castling {
+            assert_eq!(mirror(&mirror(&board)), board);
+        }
+    }
+}
+
+#[test]
+fn eval_is_symmetric_under_color_flip() {
+    for board in positions() {
+        let flipped: Board = flip(&board);
+        assert_eq!(pesto_evaluate_from_scratch(&board), pesto_evaluate_from_scratch(&flipped), "{} vs {}", board, flipped);
+        //term by term, with the colors swapped
+        let trace: EvalTrace = trace_evaluation(&board);
+        let flipped_trace: EvalTrace = trace_evaluation(&flipped);
+        assert_eq!(trace.passed_pawns.mg, [flipped_trace.passed_pawns.mg[1], flipped_trace.passed_pawns.mg[0]], "{}", board);
+        assert_eq!(trace.passed_pawns.eg, [flipped_trace.passed_pawns.eg[1], flipped_trace.passed_pawns.eg[0]], "{}", board);
+        assert_eq!(trace.psqt.mg, [flipped_trace.psqt.mg[1], flipped_trace.psqt.mg[0]], "{}", board);
+    }
+}
+
+#[test]
+fn eval_terms_without_square_tables_are_mirror_invariant() {
+    //the piece square tables are not left-right symmetric, every other term is
+    for board in positions() {
+        let mirrored: Board = mirror(&board);
+        let trace: EvalTrace = trace_evaluation(&board);
+        let mirrored_trace: EvalTrace = trace_evaluation(&mirrored);
+        assert_eq!(trace.material, mirrored_trace.material, "{}", board);
+        assert_eq!(trace.bishop_pair, mirrored_trace.bishop_pair, "{}", board);
+        assert_eq!(trace.rook_open_file, mirrored_trace.rook_open_file, "{}", board);
+        assert_eq!(trace.rook_semi_open_file, mirrored_trace.rook_semi_open_file, "{}", board);
+        assert_eq!(trace.doubled_pawns, mirrored_trace.doubled_pawns, "{}", board);
+        assert_eq!(trace.pawn_defends_friend, mirrored_trace.pawn_defends_friend, "{}", board);
+        assert_eq!(trace.passed_pawns, mirrored_trace.passed_pawns, "{}", board);
+        assert_eq!(trace.mg_phase, mirrored_trace.mg_phase, "{}", board);
+    }
+}
+
+#[test]
+fn eval_after_moves_matches_eval_from_fen() {
+    //the eval has no incremental state; this checks boards reached by playing
+    //moves evaluate the same as the same position parsed from scratch
+    for board in positions() {
+        let reparsed: Board = Board::from_fen(&board.to_string(), false).unwrap();
+        assert_eq!(reparsed.hash(), board.hash(), "{}", board);
+        assert_eq!(pesto_evaluate_from_scratch(&reparsed), pesto_evaluate_from_scratch(&board), "{}", board);
+    }
+}
+
+#[test]
+fn search_is_symmetric_under_color_flip() {
+    for fen in CORPUS {
+        let board: Board = Board::from_fen(fen, false).unwrap();
+        let flipped: Board = flip(&board);
+        let search = |board: &Board| {
+            let mut searcher: AlphaBetaSearcher = AlphaBetaSearcher::new();
+            searcher.set_verbose(false);
+            searcher.set_hash_size(16);
+            searcher.search(board, SearchLimits { depth: Some(5), ..SearchLimits::default() })
+        };
+        let result: SearchResult = search(&board);
+        let flipped_result: SearchResult = search(&flipped);
+        assert_eq!(result.score, flipped_result.score, "{}", fen);
+        assert_eq!(result.nodes, flipped_result.nodes, "{}", fen);
+        let best_move: Move = flipped_result.best_move;
+        assert_eq!(result.best_move, Move { from: best_move.from.flip_rank(), to: best_move.to.flip_rank(), promotion: best_move.promotion }, "{}", fen);
+    }
+}
@@ -0,0 +1,103 @@
+use cozy_chess::*;
+use crate::epd::*;
+use crate::notation::to_san;
+use crate::search::*;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Write};
+use std::time::Duration;
+
+/// Runs every position of an EPD suite and reports when the solution was found.
+/// A position counts as solved at the first completed iteration from which the
+/// engine never leaves a correct move again.
+pub fn run_testsuite(searcher: &mut AlphaBetaSearcher, epd_path: &str, limits: SearchLimits, report_path: &str) -> std::io::Result<()> {
+    let reader = BufReader::new(File::open(epd_path)?);
+    let mut report = File::create(report_path)?;
+    writeln!(report, "id,fen,expected,avoid,found,solved,score,depth,time_ms,nodes,comment")?;
+
+    let mut solved: u32 = 0;
+    let mut total: u32 = 0;
+    let mut total_time: Duration = Duration::ZERO;
+    searcher.set_verbose(false);
+    for line in reader.lines() {
+        let line: String = line?;
+        if line.trim().is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let entry: EpdEntry = match parse_epd_test(&line) {
+            Ok(entry) => entry,
+            Err(e) => {
+                eprintln!("Skipping EPD line: {}", e);
+                continue;
+            }
+        };
+        total += 1;
+        let id: String = if entry.id.is_empty() { format!("#{}", total) } else { entry.id.clone() };
+
+        let result: SearchResult = searcher.search(&entry.board, limits);
+        total_time += result.elapsed;
+        let found_legal: bool = entry.board.is_legal(result.best_move);
+        let is_solved: bool = found_legal && entry.is_solution(result.best_move);
+        //earliest iteration after which every iteration agreed with a solution
+        let mut first_found: Option<&IterationInfo> = None;
+        if is_solved {
+            for info in result.iterations.iter().rev() {
+                if !entry.is_solution(info.best_move) {
+                    break;
+                }
+                first_found = Some(info);
+            }
+        }
+        let (solved_depth, solved_time): (i32, Duration) = match first_found {
+            Some(info) => (info.depth, info.elapsed),
+            None => (result.depth, result.elapsed),
+        };
+        if is_solved {
+            solved += 1;
+        }
+
+        let expected: String = san_list(&entry.board, &entry.best_moves);
+        let avoid: String = san_list(&entry.board, &entry.avoid_moves);
+        let found: String = if found_legal { to_san(&entry.board, result.best_move) } else { "none".to_string() };
+        println!(
+            "{:>4} {:<16} {:<8} {}{} found {:<8} depth {:>2} time {}ms",
+            total,
+            id,
+            if is_solved { "solved" } else { "FAILED" },
+            if expected.is_empty() { String::new() } else { format!("bm {} ", expected) },
+            if avoid.is_empty() { String::new() } else { format!("am {} ", avoid) },
+            found,
+            solved_depth,
+            solved_time.as_millis(),
+        );
+        writeln!(
+            report,
+            "{},{},{},{},{},{},{},{},{},{},{}",
+            csv_field(&id),
+            csv_field(&entry.board.to_string()),
+            csv_field(&expected),
+            csv_field(&avoid),
+            csv_field(&found),
+            is_solved,
+            result.score,
+            solved_depth,
+            solved_time.as_millis(),
+            result.nodes,
+            csv_field(&entry.comment),
+        )?;
+    }
+    searcher.set_verbose(true);
+    println!("solved {}/{} in {:.1}s, report written to {}", solved, total, total_time.as_secs_f32(), report_path);
+    Ok(())
+}
+
+fn san_list(board: &Board, moves: &[Move]) -> String {
+    moves.iter().map(|m| to_san(board, *m)).collect::<Vec<String>>().join(" ")
+}
+
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
@@ -0,0 +1,546 @@
+use cozy_chess::*;
+use cozy_chess::util::{display_uci_move, parse_uci_move};
+use crate::epd::parse_epd_line;
+use crate::pgn::*;
+use crate::sprt::*;
+use rand::seq::SliceRandom;
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Error, ErrorKind, Write};
+use std::process::{Child, ChildStdin, Command, Stdio};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+#[derive(Clone, Copy)]
+pub struct TimeControl {
+    pub base_ms: u64,
+    pub increment_ms: u64,
+}
+
+impl TimeControl {
+    /// Parses fastchess/cutechess notation in seconds, e.g. `4+0.04`.
+    pub fn parse(tc: &str) -> Option<TimeControl> {
+        let (base, increment) = tc.split_once('+').unwrap_or((tc, "0"));
+        let base: f64 = base.parse().ok()?;
+        let increment: f64 = increment.parse().ok()?;
+        Some(TimeControl {
+            base_ms: (base * 1000.0) as u64,
+            increment_ms: (increment * 1000.0) as u64,
+        })
+    }
+}
+
+impl std::fmt::Display for TimeControl {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}+{}", self.base_ms as f64 / 1000.0, self.increment_ms as f64 / 1000.0)
+    }
+}
+
+pub struct MatchConfig {
+    pub engines: [String; 2], //[dev, baseline] commands; results are reported for the first
+    pub time_control: TimeControl,
+    pub openings: Option<String>, //EPD or PGN file, startpos if unset
+    pub concurrency: usize,
+    pub rounds: usize, //game pairs
+    pub pgn_out: Option<String>,
+    pub sprt: SprtConfig,
+    pub rating_interval: usize, //in game pairs
+    pub draw_movenumber: u16, //same defaults as `-draw movenumber=80 movecount=8 score=15`
+    pub draw_movecount: u32,
+    pub draw_score: i32,
+    pub resign_movecount: u32,
+    pub resign_score: i32,
+}
+
+impl Default for MatchConfig {
+    fn default() -> Self {
+        MatchConfig {
+            engines: [String::new(), String::new()],
+            time_control: TimeControl { base_ms: 4000, increment_ms: 40 },
+            openings: None,
+            concurrency: 1,
+            rounds: 100000,
+            pgn_out: None,
+            sprt: SprtConfig::default(),
+            rating_interval: 10,
+            draw_movenumber: 80,
+            draw_movecount: 8,
+            draw_score: 15,
+            resign_movecount: 3,
+            resign_score: 1000,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum GameResult {
+    WhiteWins,
+    BlackWins,
+    Draw,
+}
+
+impl GameResult {
+    fn pgn(&self) -> &'static str {
+        match self {
+            GameResult::WhiteWins => "1-0",
+            GameResult::BlackWins => "0-1",
+            GameResult::Draw => "1/2-1/2",
+        }
+    }
+    fn loss_for(color: Color) -> GameResult {
+        match color {
+            Color::White => GameResult::BlackWins,
+            Color::Black => GameResult::WhiteWins,
+        }
+    }
+    /// Half points scored by the side playing `color`: 2 for a win, 1 for a draw.
+    fn half_points(&self, color: Color) -> usize {
+        match *self {
+            GameResult::Draw => 1,
+            r if r == GameResult::loss_for(!color) => 2,
+            _ => 0,
+        }
+    }
+}
+
+struct Opening {
+    board: Board,
+    moves: Vec<Move>, //book moves from a PGN opening, played before the engines take over
+}
+
+struct GameRecord {
+    result: GameResult,
+    pgn: PgnGame,
+}
+
+struct Engine {
+    child: Child,
+    stdin: ChildStdin,
+    lines: Receiver<String>,
+    options: Vec<String>, //names of the options advertised before uciok
+}
+
+impl Engine {
+    fn start(command: &str) -> std::io::Result<Engine> {
+        let mut parts = command.split_whitespace();
+        let program: &str = parts.next().ok_or_else(|| Error::new(ErrorKind::InvalidInput, "empty engine command"))?;
+        let mut child: Child = Command::new(program)
+            .args(parts)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()?;
+        let stdin: ChildStdin = child.stdin.take().unwrap();
+        let stdout = child.stdout.take().unwrap();
+        //a reader thread lets us wait for engine output with a timeout
+        let (sender, lines) = mpsc::channel();
+        thread::spawn(move || {
+            for line in BufReader::new(stdout).lines() {
+                match line {
+                    Ok(line) => if sender.send(line).is_err() { break },
+                    Err(_) => break,
+                }
+            }
+        });
+        let mut engine: Engine = Engine { child, stdin, lines, options: Vec::new() };
+        engine.send("uci")?;
+        engine.options = engine.wait_for("uciok", Duration::from_secs(10))?.iter()
+            .filter_map(|line| line.strip_prefix("option name ")?.split(" type ").next().map(str::to_string))
+            .collect();
+        Ok(engine)
+    }
+
+    fn send(&mut self, command: &str) -> std::io::Result<()> {
+        writeln!(self.stdin, "{}", command)?;
+        self.stdin.flush()
+    }
+
+    /// Collects output lines up to and including the first one starting with `token`.
+    fn wait_for(&mut self, token: &str, timeout: Duration) -> std::io::Result<Vec<String>> {
+        let deadline: Instant = Instant::now() + timeout;
+        let mut lines: Vec<String> = Vec::new();
+        loop {
+            match self.lines.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+                Ok(line) => {
+                    let done: bool = line.starts_with(token);
+                    lines.push(line);
+                    if done {
+                        return Ok(lines);
+                    }
+                },
+                Err(RecvTimeoutError::Timeout) => return Err(Error::new(ErrorKind::TimedOut, format!("engine did not send {}", token))),
+                Err(RecvTimeoutError::Disconnected) => return Err(Error::new(ErrorKind::UnexpectedEof, "engine exited")),
+            }
+        }
+    }
+
+    fn new_game(&mut self) -> std::io::Result<()> {
+        self.send("ucinewgame")?;
+        self.send("isready")?;
+        self.wait_for("readyok", Duration::from_secs(10))?;
+        Ok(())
+    }
+}
+
+impl Drop for Engine {
+    fn drop(&mut self) {
+        let _ = self.send("quit");
+        let deadline: Instant = Instant::now() + Duration::from_millis(500);
+        while Instant::now() < deadline {
+            if let Ok(Some(_)) = self.child.try_wait() {
+                return;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+const MATE_SCORE: i32 = 100000;
+
+/// Score and depth of the last `info ... score` line, from the engine's point of view.
+/// Mate in n is reported as `MATE_SCORE - n`.
+fn parse_info(lines: &[String]) -> Option<(i32, i32)> {
+    lines.iter().rev().filter(|l| l.starts_with("info")).find_map(|line| {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let i: usize = words.iter().position(|&w| w == "score")?;
+        let value: i32 = words.get(i + 2)?.parse().ok()?;
+        let score: i32 = match *words.get(i + 1)? {
+            "cp" => value,
+            "mate" if value > 0 => MATE_SCORE - value,
+            "mate" => -MATE_SCORE - value,
+            _ => return None,
+        };
+        let depth: i32 = words.iter().position(|&w| w == "depth").and_then(|d| words.get(d + 1)?.parse().ok()).unwrap_or(0);
+        Some((score, depth))
+    })
+}
+
+/// Move comment in the same style as fastchess, e.g. `+0.31/9 0.112s`.
+fn move_comment(info: Option<(i32, i32)>, elapsed: Duration) -> String {
+    let score: String = match info {
+        Some((score, _)) if score.abs() > MATE_SCORE - 1000 => {
+            format!("{}M{}", if score > 0 { "+" } else { "-" }, MATE_SCORE - score.abs())
+        },
+        Some((score, _)) => format!("{:+.2}", score as f64 / 100.0),
+        None => "?".to_string(),
+    };
+    let depth: i32 = info.map_or(0, |(_, depth)| depth);
+    format!("{}/{} {:.3}s", score, depth, elapsed.as_secs_f64())
+}
+
+/// Neither side can mate: no pawns, rooks or queens and at most one minor piece.
+pub fn insufficient_material(board: &Board) -> bool {
+    let heavy: BitBoard = board.pieces(Piece::Pawn) | board.pieces(Piece::Rook) | board.pieces(Piece::Queen);
+    let minors: BitBoard = board.pieces(Piece::Knight) | board.pieces(Piece::Bishop);
+    heavy.is_empty() && minors.len() <= 1
+}
+
+fn play_game(engines: &mut [Engine; 2], names: &[String; 2], white: usize, opening: &Opening, config: &MatchConfig) -> std::io::Result<GameRecord> {
+    for engine in engines.iter_mut() {
+        engine.new_game()?;
+    }
+    let start: Board = opening.board.clone();
+    let mut board: Board = start.clone();
+    let mut pgn: PgnGame = PgnGame::new(start.clone());
+    let mut uci_moves: Vec<String> = Vec::new();
+    let mut seen: Vec<u64> = vec![board.hash()];
+    for m in &opening.moves {
+        uci_moves.push(display_uci_move(&board, *m).to_string());
+        let mut book_move: PgnMove = PgnMove::new(*m);
+        book_move.comment = Some("book".to_string());
+        pgn.moves.push(book_move);
+        board.play(*m);
+        seen.push(board.hash());
+    }
+    let tc: TimeControl = config.time_control;
+    let mut clocks: [i64; 2] = [tc.base_ms as i64; 2]; //indexed by color
+    let mut draw_plies: u32 = 0;
+    //consecutive moves on which each color's engine reported itself lost or won
+    let mut losing_moves: [u32; 2] = [0; 2];
+    let mut winning_moves: [u32; 2] = [0; 2];
+
+    let (result, termination): (GameResult, &'static str) = loop {
+        match board.status() {
+            GameStatus::Won => break (GameResult::loss_for(board.side_to_move()), "checkmate"),
+            GameStatus::Drawn if board.halfmove_clock() >= 100 => break (GameResult::Draw, "fifty move rule"),
+            GameStatus::Drawn => break (GameResult::Draw, "stalemate"),
+            GameStatus::Ongoing => (),
+        }
+        if seen.iter().filter(|&&h| h == board.hash()).count() >= 3 {
+            break (GameResult::Draw, "threefold repetition");
+        }
+        if insufficient_material(&board) {
+            break (GameResult::Draw, "insufficient material");
+        }
+        let side: Color = board.side_to_move();
+        let engine: &mut Engine = &mut engines[if side == Color::White { white } else { 1 - white }];
+        engine.send(&format!("position fen {} moves {}", start, uci_moves.join(" ")))?;
+        engine.send(&format!(
+            "go wtime {} btime {} winc {} binc {}",
+            clocks[Color::White as usize].max(1), clocks[Color::Black as usize].max(1), tc.increment_ms, tc.increment_ms
+        ))?;
+        let move_start: Instant = Instant::now();
+        let timeout: Duration = Duration::from_millis(clocks[side as usize].max(0) as u64 + 1000);
+        let lines: Vec<String> = match engine.wait_for("bestmove", timeout) {
+            Ok(lines) => lines,
+            Err(e) if e.kind() == ErrorKind::TimedOut => break (GameResult::loss_for(side), "time forfeit"),
+            Err(e) => return Err(e),
+        };
+        let elapsed: Duration = move_start.elapsed();
+        clocks[side as usize] -= elapsed.as_millis() as i64;
+        if clocks[side as usize] < 0 {
+            break (GameResult::loss_for(side), "time forfeit");
+        }
+        clocks[side as usize] += tc.increment_ms as i64;
+
+        let bestmove: &str = lines.last().unwrap().split_whitespace().nth(1).unwrap_or("");
+        let m: Move = match parse_uci_move(&board, bestmove) {
+            Ok(m) if board.is_legal(m) => m,
+            _ => break (GameResult::loss_for(side), "illegal move"),
+        };
+
+        //adjudication uses the engines' own evaluations; move numbers count from the
+        //start of the game rather than the opening's FEN, so late book exits are not cut short
+        let info: Option<(i32, i32)> = parse_info(&lines);
+        if let Some((score, _)) = info {
+            let move_number: usize = uci_moves.len() / 2 + 1;
+            if move_number >= config.draw_movenumber as usize && score.abs() <= config.draw_score {
+                draw_plies += 1;
+            } else {
+                draw_plies = 0;
+            }
+            if score <= -config.resign_score {
+                losing_moves[side as usize] += 1;
+            } else {
+                losing_moves[side as usize] = 0;
+            }
+            if score >= config.resign_score {
+                winning_moves[side as usize] += 1;
+            } else {
+                winning_moves[side as usize] = 0;
+            }
+        }
+
+        uci_moves.push(display_uci_move(&board, m).to_string());
+        let mut played: PgnMove = PgnMove::new(m);
+        played.comment = Some(move_comment(info, elapsed));
+        pgn.moves.push(played);
+        board.play(m);
+        seen.push(board.hash());
+        if draw_plies >= 2 * config.draw_movecount {
+            break (GameResult::Draw, "adjudication");
+        }
+        //resign only when both engines agree on who is lost
+        if let Some(loser) = Color::ALL.into_iter().find(|&c| losing_moves[c as usize] >= config.resign_movecount && winning_moves[!c as usize] >= config.resign_movecount) {
+            break (GameResult::loss_for(loser), "adjudication");
+        }
+    };
+    pgn.set_tag("Event", "rustbot match");
+    pgn.set_tag("Site", "?");
+    pgn.set_tag("Round", "?");
+    pgn.set_tag("White", &names[white]);
+    pgn.set_tag("Black", &names[1 - white]);
+    pgn.set_tag("Result", result.pgn());
+    if start != Board::default() {
+        pgn.set_tag("FEN", &start.to_string());
+        pgn.set_tag("SetUp", "1");
+    }
+    pgn.set_tag("TimeControl", &tc.to_string());
+    pgn.set_tag("Termination", termination);
+    pgn.result = result.pgn().to_string();
+    Ok(GameRecord { result, pgn })
+}
+
+fn load_openings(path: &Option<String>) -> std::io::Result<Vec<Opening>> {
+    let Some(path) = path else {
+        return Ok(vec![Opening { board: Board::default(), moves: Vec::new() }]);
+    };
+    let mut openings: Vec<Opening> = Vec::new();
+    if path.ends_with(".pgn") {
+        let text: String = std::fs::read_to_string(path)?;
+        for game in parse_pgn_games(&text) {
+            match game {
+                Ok(game) => openings.push(Opening { board: game.start, moves: game.moves.iter().map(|m| m.mv).collect() }),
+                Err(e) => eprintln!("Skipping opening: {}", e),
+            }
+        }
+    } else {
+        for line in BufReader::new(File::open(path)?).lines() {
+            let line: String = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            match parse_epd_line(&line) {
+                Ok(entry) => openings.push(Opening { board: entry.board, moves: Vec::new() }),
+                Err(e) => eprintln!("Skipping opening: {}", e),
+            }
+        }
+    }
+    if openings.is_empty() {
+        return Err(Error::new(ErrorKind::InvalidData, format!("no openings in {}", path)));
+    }
+    openings.shuffle(&mut rand::thread_rng());
+    Ok(openings)
+}
+
+fn engine_names(commands: &[String; 2]) -> [String; 2] {
+    let name = |command: &str| -> String {
+        let program: &str = command.split_whitespace().next().unwrap_or("engine");
+        std::path::Path::new(program).file_name().map_or(program.to_string(), |f| f.to_string_lossy().to_string())
+    };
+    let names: [String; 2] = [name(&commands[0]), name(&commands[1])];
+    if names[0] == names[1] {
+        return ["dev".to_string(), "baseline".to_string()];
+    }
+    names
+}
+
+fn print_status(names: &[String; 2], wdl: [u64; 3], pentanomial: &Pentanomial, sprt: &SprtConfig) {
+    let (elo, margin) = pentanomial.elo();
+    let (lower, upper) = sprt.bounds();
+    let llr: f64 = pentanomial.llr(sprt);
+    let games: u64 = wdl.iter().sum();
+    println!("Results of {} vs {}:", names[0], names[1]);
+    println!(
+        "Elo: {:.2} +/- {:.2}, LOS: {:.1} %, DrawRatio: {:.1} %",
+        elo, margin, pentanomial.los() * 100.0, wdl[1] as f64 * 100.0 / games.max(1) as f64
+    );
+    println!(
+        "SPRT ({:.2}, {:.2}): llr {:.2} ({:.1}%), lbound {:.2}, ubound {:.2}",
+        sprt.elo0, sprt.elo1, llr, llr * 100.0 / upper, lower, upper
+    );
+    println!("Games: {}, Wins: {}, Losses: {}, Draws: {}", games, wdl[0], wdl[2], wdl[1]);
+    println!("Ptnml(0-2): {:?}", pentanomial.counts);
+}
+
+/// Plays color-reversed game pairs between two UCI engines until the SPRT
+/// concludes or `rounds` pairs have been played.
+pub fn run_match(config: MatchConfig) -> std::io::Result<()> {
+    let openings: Arc<Vec<Opening>> = Arc::new(load_openings(&config.openings)?);
+    let names: [String; 2] = engine_names(&config.engines);
+    let mut pgn: Option<BufWriter<File>> = match &config.pgn_out {
+        Some(path) => Some(BufWriter::new(File::create(path)?)),
+        None => None,
+    };
+    let config: Arc<MatchConfig> = Arc::new(config);
+    let next_pair: Arc<AtomicUsize> = Arc::new(AtomicUsize::new(0));
+    let stop: Arc<AtomicBool> = Arc::new(AtomicBool::new(false));
+    let (sender, results) = mpsc::channel::<(usize, GameRecord, GameRecord)>();
+    println!(
+        "Match {} vs {}: tc {}, {} openings, concurrency {}",
+        names[0], names[1], config.time_control, openings.len(), config.concurrency
+    );
+
+    let mut workers = Vec::new();
+    for _ in 0..config.concurrency.max(1) {
+        let (openings, config, next_pair, stop, sender) = (openings.clone(), config.clone(), next_pair.clone(), stop.clone(), sender.clone());
+        let names: [String; 2] = names.clone();
+        workers.push(thread::spawn(move || {
+            let mut engines: [Engine; 2] = match (Engine::start(&config.engines[0]), Engine::start(&config.engines[1])) {
+                (Ok(a), Ok(b)) => [a, b],
+                (Err(e), _) | (_, Err(e)) => {
+                    eprintln!("Failed to start engine. Error: {:?}", e);
+                    return;
+                }
+            };
+            while !stop.load(Ordering::Relaxed) {
+                let pair: usize = next_pair.fetch_add(1, Ordering::Relaxed);
+                if pair >= config.rounds {
+                    break;
+                }
+                let opening: &Opening = &openings[pair % openings.len()];
+                let games = play_game(&mut engines, &names, 0, opening, &config)
+                    .and_then(|first| play_game(&mut engines, &names, 1, opening, &config).map(|second| (first, second)));
+                match games {
+                    Ok((first, second)) => if sender.send((pair, first, second)).is_err() { break },
+                    Err(e) => {
+                        eprintln!("Engine failure in pair {}. Error: {:?}", pair + 1, e);
+                        break;
+                    }
+                }
+            }
+        }));
+    }
+    drop(sender);
+
+    let mut wdl: [u64; 3] = [0; 3]; //from the first engine's point of view
+    let mut pentanomial: Pentanomial = Pentanomial::default();
+    let (lower, upper) = config.sprt.bounds();
+    for (pair, mut first, mut second) in results.iter() {
+        if stop.load(Ordering::Relaxed) {
+            continue; //drain games that were running when the SPRT finished
+        }
+        let mut half_points: usize = 0;
+        for (game, dev_color) in [(&first, Color::White), (&second, Color::Black)] {
+            let points: usize = game.result.half_points(dev_color);
+            wdl[2 - points] += 1;
+            half_points += points;
+        }
+        pentanomial.add_pair(half_points);
+        if let Some(out) = pgn.as_mut() {
+            for (i, game) in [&mut first, &mut second].into_iter().enumerate() {
+                game.pgn.set_tag("Round", &format!("{}.{}", pair + 1, i + 1));
+                write_pgn(out, &game.pgn)?;
+            }
+            out.flush()?;
+        }
+        let llr: f64 = pentanomial.llr(&config.sprt);
+        let finished: bool = llr <= lower || llr >= upper;
+        if finished || (pentanomial.pairs() as usize).is_multiple_of(config.rating_interval.max(1)) {
+            print_status(&names, wdl, &pentanomial, &config.sprt);
+        }
+        if finished {
+            println!("SPRT: H{} was accepted", if llr >= upper { 1 } else { 0 });
+            stop.store(true, Ordering::Relaxed);
+        }
+    }
+    for worker in workers {
+        let _ = worker.join();
+    }
+    if !stop.load(Ordering::Relaxed) {
+        print_status(&names, wdl, &pentanomial, &config.sprt);
+    }
+    Ok(())
+}
+
+/// Plays single game pairs on demand, with the engines' UCI options set before each
+/// pair, for tuners that change the engines between pairs. The match's PGN, SPRT and
+/// concurrency settings are not used.
+pub struct PairPlayer {
+    config: MatchConfig,
+    names: [String; 2],
+    openings: Vec<Opening>,
+    engines: [Engine; 2],
+    pairs: usize,
+}
+
+impl PairPlayer {
+    pub fn new(config: MatchConfig) -> std::io::Result<PairPlayer> {
+        let openings: Vec<Opening> = load_openings(&config.openings)?;
+        let engines: [Engine; 2] = [Engine::start(&config.engines[0])?, Engine::start(&config.engines[1])?];
+        let names: [String; 2] = engine_names(&config.engines);
+        Ok(PairPlayer { config, names, openings, engines, pairs: 0 })
+    }
+
+    /// Sends each engine its `setoption`s, then plays the next opening with both colors.
+    /// Returns the first engine's half points, 0 to 4.
+    pub fn play_pair(&mut self, options: [&[(&str, i32)]; 2]) -> std::io::Result<usize> {
+        for (engine, options) in self.engines.iter_mut().zip(options) {
+            for (name, value) in options {
+                if !engine.options.iter().any(|o| o == name) {
+                    return Err(Error::new(ErrorKind::InvalidInput, format!("engine has no option {}", name)));
+                }
+                engine.send(&format!("setoption name {} value {}", name, value))?;
+            }
+        }
+        let opening: &Opening = &self.openings[self.pairs % self.openings.len()];
+        self.pairs += 1;
+        let first: GameRecord = play_game(&mut self.engines, &self.names, 0, opening, &self.config)?;
+        let second: GameRecord = play_game(&mut self.engines, &self.names, 1, opening, &self.config)?;
+        Ok(first.result.half_points(Color::White) + second.result.half_points(Color::Black))
+    }
+}
@@ -0,0 +1,264 @@
+use cozy_chess::*;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Error, ErrorKind, Read, Write};
+use std::mem::MaybeUninit;
+use std::str::FromStr;
+
+/// Default table size in MB, the UCI `Hash` option.
+pub const DEFAULT_HASH_MB: usize = 256;
+pub const MAX_HASH_MB: usize = 1 << 16;
+/// Hash files start with this magic and version, then the start position's hash so a
+/// file written by a build whose `board.hash()` differs is rejected.
+const HASH_FILE_MAGIC: [u8; 4] = *b"RBTT";
+const HASH_FILE_VERSION: u32 = 1;
+const SERIALIZED_ENTRY_SIZE: usize = 24;
+
+#[derive(Clone, Copy)]
+pub struct TTEntry {
+    pub hash: u64,
+    pub depth: i32,
+    pub score: i32,
+    pub static_eval: i32, //saves re-evaluating on a hit, meaningless if the position is in check
+    pub best_move: Move,
+    pub node_type: NodeType,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum NodeType {
+    Exact,
+    LowerBound,
+    UpperBound,
+}
+
+impl TTEntry {
+    fn empty() -> Self {
+        TTEntry {
+            hash: 0,
+            depth: 0,
+            score: 0,
+            static_eval: 0,
+            best_move: Move::from_str("a1a1").unwrap(),
+            node_type: NodeType::Exact,
+        }
+    }
+
+    fn serialize(&self) -> [u8; SERIALIZED_ENTRY_SIZE] {
+        let mut bytes: [u8; SERIALIZED_ENTRY_SIZE] = [0; SERIALIZED_ENTRY_SIZE];
+        bytes[0..8].copy_from_slice(&self.hash.to_le_bytes());
+        bytes[8..12].copy_from_slice(&self.depth.to_le_bytes());
+        bytes[12..16].copy_from_slice(&self.score.to_le_bytes());
+        bytes[16..20].copy_from_slice(&self.static_eval.to_le_bytes());
+        bytes[20] = self.best_move.from as u8;
+        bytes[21] = self.best_move.to as u8;
+        bytes[22] = self.best_move.promotion.map_or(u8::MAX, |p| p as u8);
+        bytes[23] = self.node_type as u8;
+        bytes
+    }
+
+    fn deserialize(bytes: &[u8; SERIALIZED_ENTRY_SIZE]) -> Option<Self> {
+        let int = |i: usize| i32::from_le_bytes(bytes[i..i + 4].try_into().unwrap());
+        let promotion: Option<Piece> = match bytes[22] {
+            u8::MAX => None,
+            p => Some(Piece::try_index(p as usize)?),
+        };
+        Some(TTEntry {
+            hash: u64::from_le_bytes(bytes[0..8].try_into().unwrap()),
+            depth: int(8),
+            score: int(12),
+            static_eval: int(16),
+            best_move: Move {
+                from: Square::try_index(bytes[20] as usize)?,
+                to: Square::try_index(bytes[21] as usize)?,
+                promotion,
+            },
+            node_type: match bytes[23] {
+                0 => NodeType::Exact,
+                1 => NodeType::LowerBound,
+                2 => NodeType::UpperBound,
+                _ => return None,
+            },
+        })
+    }
+}
+
+/// Entries grouped so that none straddles two cache lines, and so the table is
+/// allocated at cache line alignment. A 24 byte entry leaves 16 bytes of each line
+/// unused: packing it to 16 would mean a partial key and 16 bit scores, which
+/// cannot hold the mate scores, so the space is traded for full keys and scores.
+#[derive(Clone, Copy)]
+#[repr(align(64))]
+struct CacheLine([TTEntry; ENTRIES_PER_LINE]);
+
+const ENTRIES_PER_LINE: usize = 64 / std::mem::size_of::<TTEntry>();
+
+/// Runs `fill` over `lines` split across the machine's cores. The search itself is
+/// single threaded, so there is no thread count to configure.
+fn split_over_cores<T: Send>(lines: &mut [T], fill: impl Fn(&mut [T]) + Sync) {
+    let threads: usize = std::thread::available_parallelism().map_or(1, |n| n.get());
+    let chunk: usize = lines.len().div_ceil(threads).max(1);
+    let fill = &fill;
+    std::thread::scope(|scope| {
+        for part in lines.chunks_mut(chunk) {
+            scope.spawn(move || fill(part));
+        }
+    });
+}
+
+/// Always-replace hash table of search results, keyed by `board.hash()`. Memory is
+/// only allocated by `allocate`, so starting the engine and resizing stay cheap.
+pub struct TranspositionTable {
+    lines: Vec<CacheLine>,
+    size_mb: usize,
+}
+
+impl TranspositionTable {
+    pub fn new(size_mb: usize) -> Self {
+        TranspositionTable { lines: Vec::new(), size_mb }
+    }
+
+    /// Frees the table; the next `allocate` creates it with the new size.
+    pub fn resize(&mut self, size_mb: usize) {
+        self.size_mb = size_mb.clamp(1, MAX_HASH_MB);
+        self.lines = Vec::new();
+    }
+
+    /// Allocates the table if it is not allocated yet, writing the empty entries on
+    /// every core. Must be called before probing.
+    pub fn allocate(&mut self) {
+        if self.lines.is_empty() {
+            let count: usize = self.size_mb * 1024 * 1024 / std::mem::size_of::<CacheLine>();
+            let mut lines: Box<[MaybeUninit<CacheLine>]> = Box::new_uninit_slice(count);
+            split_over_cores(&mut lines, |part| {
+                for line in part {
+                    line.write(CacheLine([TTEntry::empty(); ENTRIES_PER_LINE]));
+                }
+            });
+            //SAFETY: every line was written just above
+            self.lines = unsafe { lines.assume_init() }.into_vec();
+        }
+    }
+
+    /// Empties every entry, with the work split over the machine's cores.
+    pub fn clear(&mut self) {
+        split_over_cores(&mut self.lines, |part| part.fill(CacheLine([TTEntry::empty(); ENTRIES_PER_LINE])));
+    }
+
+    /// Maps `hash` onto the table with a multiply-shift, which works for any size
+    /// and uses the hash's high bits.
+    fn index(&self, hash: u64) -> (usize, usize) {
+        let entry: usize = ((hash as u128 * (self.lines.len() * ENTRIES_PER_LINE) as u128) >> 64) as usize;
+        (entry / ENTRIES_PER_LINE, entry % ENTRIES_PER_LINE)
+    }
+
+    /// The entry in `hash`'s slot; it belongs to another position unless its `hash` matches.
+    pub fn probe(&self, hash: u64) -> TTEntry {
+        let (line, slot) = self.index(hash);
+        self.lines[line].0[slot]
+    }
+
+    pub fn store(&mut self, entry: TTEntry) {
+        let (line, slot) = self.index(entry.hash);
+        self.lines[line].0[slot] = entry;
+    }
+
+    /// Occupied entries per thousand, sampled from the start of the table.
+    pub fn hashfull(&self) -> usize {
+        let sample: Vec<&TTEntry> = self.lines.iter().flat_map(|line| &line.0).take(1000).collect();
+        sample.iter().filter(|e| e.hash != 0).count() * 1000 / sample.len().max(1)
+    }
+
+    /// Writes the occupied entries to `path`. Returns how many were written.
+    pub fn save(&self, path: &str) -> std::io::Result<usize> {
+        let mut out = BufWriter::new(File::create(path)?);
+        let occupied: Vec<&TTEntry> = self.lines.iter().flat_map(|line| &line.0).filter(|e| e.hash != 0).collect();
+        out.write_all(&HASH_FILE_MAGIC)?;
+        out.write_all(&HASH_FILE_VERSION.to_le_bytes())?;
+        out.write_all(&Board::default().hash().to_le_bytes())?;
+        out.write_all(&(occupied.len() as u64).to_le_bytes())?;
+        for entry in &occupied {
+            out.write_all(&entry.serialize())?;
+        }
+        out.flush()?;
+        Ok(occupied.len())
+    }
+
+    /// Adds the entries saved in `path`, replacing whatever shares their slots. Entries
+    /// are placed by their hash, so a file saved from a table of another size still loads.
+    /// Returns how many entries were read.
+    pub fn load(&mut self, path: &str) -> std::io::Result<usize> {
+        self.allocate();
+        let mut input = BufReader::new(File::open(path)?);
+        let mut header: [u8; 24] = [0; 24];
+        input.read_exact(&mut header)?;
+        if header[0..4] != HASH_FILE_MAGIC {
+            return Err(Error::new(ErrorKind::InvalidData, "not a hash file"));
+        }
+        let version: u32 = u32::from_le_bytes(header[4..8].try_into().unwrap());
+        if version != HASH_FILE_VERSION {
+            return Err(Error::new(ErrorKind::InvalidData, format!("unsupported hash file version {}", version)));
+        }
+        if u64::from_le_bytes(header[8..16].try_into().unwrap()) != Board::default().hash() {
+            return Err(Error::new(ErrorKind::InvalidData, "hash file was written with a different position hash function"));
+        }
+        let count: u64 = u64::from_le_bytes(header[16..24].try_into().unwrap());
+        let mut bytes: [u8; SERIALIZED_ENTRY_SIZE] = [0; SERIALIZED_ENTRY_SIZE];
+        for _ in 0..count {
+            input.read_exact(&mut bytes)?;
+            let entry: TTEntry = TTEntry::deserialize(&bytes).ok_or_else(|| Error::new(ErrorKind::InvalidData, "corrupt hash file entry"))?;
+            self.store(entry);
+        }
+        Ok(count as usize)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn saved_table_loads_back_and_foreign_files_are_rejected() {
+        let path: String = std::env::temp_dir().join(format!("rustbot_tt_test_{}.bin", std::process::id())).to_string_lossy().into_owned();
+        let mut table: TranspositionTable = TranspositionTable::new(1);
+        table.allocate();
+        let entry: TTEntry = TTEntry {
+            hash: Board::default().hash(),
+            depth: 12,
+            score: -35,
+            static_eval: 20,
+            best_move: "e7e8n".parse().unwrap(),
+            node_type: NodeType::LowerBound,
+        };
+        table.store(entry);
+        assert_eq!(table.save(&path).unwrap(), 1);
+
+        //a different size, so every entry is rehashed into its new slot
+        let mut loaded: TranspositionTable = TranspositionTable::new(3);
+        assert_eq!(loaded.load(&path).unwrap(), 1);
+        let probed: TTEntry = loaded.probe(entry.hash);
+        assert_eq!((probed.hash, probed.depth, probed.score, probed.static_eval), (entry.hash, 12, -35, 20));
+        assert_eq!((probed.best_move, probed.node_type), (entry.best_move, entry.node_type));
+
+        //same layout, but hashed by some other function
+        let mut bytes: Vec<u8> = std::fs::read(&path).unwrap();
+        bytes[8] ^= 1;
+        std::fs::write(&path, bytes).unwrap();
+        assert_eq!(loaded.load(&path).unwrap_err().kind(), ErrorKind::InvalidData);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn any_size_indexes_in_range_and_clears() {
+        let mut table: TranspositionTable = TranspositionTable::new(3);
+        table.allocate();
+        let mut hash: u64 = 1;
+        for _ in 0..10000 {
+            hash = hash.wrapping_mul(0x9E3779B97F4A7C15).wrapping_add(7);
+            table.store(TTEntry { hash, ..TTEntry::empty() });
+            assert_eq!(table.probe(hash).hash, hash);
+        }
+        assert!(table.hashfull() > 0);
+        table.clear();
+        assert_eq!(table.probe(hash).hash, 0);
+        assert_eq!(table.hashfull(), 0);
+    }
+}
@@ -0,0 +1,130 @@
+#[cfg(feature = "tune")]
+use std::sync::atomic::{AtomicI32, Ordering};
+
+/// A search parameter with the range and SPSA step a tuner should use.
+pub struct Tunable {
+    pub name: &'static str,
+    pub default: i32,
+    pub min: i32,
+    pub max: i32,
+    pub step: i32,
+}
+
+/// Declares each parameter as a function returning its value. Normal builds get
+/// the default as a constant; `--features tune` builds read it from a global that
+/// `set` changes, so the parameters can be tuned through UCI options.
+macro_rules! tunables {
+    ($($name:ident: $default:expr, $min:expr, $max:expr, $step:expr;)*) => {
+        /// Every tunable parameter, in declaration order.
+        pub const TUNABLES: &[Tunable] = &[
+            $(Tunable { name: stringify!($name), default: $default, min: $min, max: $max, step: $step },)*
+        ];
+
+        #[cfg(feature = "tune")]
+        #[allow(non_upper_case_globals)]
+        mod values {
+            use super::AtomicI32;
+            $(pub static $name: AtomicI32 = AtomicI32::new($default);)*
+        }
+
+        $(
+            #[cfg(not(feature = "tune"))]
+            #[inline(always)]
+            pub fn $name() -> i32 {
+                $default
+            }
+            #[cfg(feature = "tune")]
+            #[inline(always)]
+            pub fn $name() -> i32 {
+                values::$name.load(Ordering::Relaxed)
+            }
+        )*
+
+        /// Sets the parameter called `name`, clamped to its range. Returns false for an unknown name.
+        #[cfg(feature = "tune")]
+        pub fn set(name: &str, value: i32) -> bool {
+            match name {
+                $(stringify!($name) => values::$name.store(value.clamp($min, $max), Ordering::Relaxed),)*
+                _ => return false,
+            }
+            true
+        }
+    };
+}
+
+tunables! {
+    //reverse futility pruning: margin per ply, below this depth
+    rfp_margin: 90, 40, 200, 10;
+    rfp_max_depth: 8, 4, 12, 1;
+    //futility pruning: margin per ply, extra margin when improving, below this depth
+    futility_margin: 160, 80, 300, 15;
+    futility_improving: 60, 0, 150, 10;
+    futility_max_depth: 5, 2, 8, 1;
+    //null move pruning: depth reduction, above this depth
+    nmp_reduction: 3, 2, 5, 1;
+    nmp_min_depth: 3, 1, 6, 1;
+    //razoring: margin per ply, up to this depth
+    razor_margin: 250, 100, 500, 20;
+    razor_max_depth: 2, 1, 4, 1;
+    //probcut: margin over beta, from this depth, searched this much shallower
+    probcut_margin: 200, 80, 400, 15;
+    probcut_min_depth: 5, 3, 8, 1;
+    probcut_reduction: 4, 2, 6, 1;
+    //singular extensions: from this depth, with a TT entry at most this much shallower,
+    //the other moves searched against the TT score minus this much per ply
+    se_min_depth: 8, 5, 12, 1;
+    se_tt_depth_margin: 3, 1, 6, 1;
+    se_beta_scale: 2, 1, 5, 1;
+    //late move pruning: up to this depth, quiets after lmp_base + depth^2 (halved when not improving)
+    lmp_max_depth: 4, 2, 8, 1;
+    lmp_base: 3, 0, 8, 1;
+    //qsearch delta pruning margin over the captured piece's value
+    delta_margin: 200, 50, 500, 25;
+    //initial aspiration window half-width in cp
+    aspiration_delta: 15, 5, 60, 3;
+    //late move reductions in 1/1024 ply, the table divisor in 1/100
+    lmr_base: 768, 0, 2048, 64;
+    lmr_divisor: 225, 150, 400, 10;
+    lmr_min_depth: 3, 2, 6, 1;
+    lmr_pv: 1024, 0, 3072, 128;
+    lmr_cut_node: 1024, 0, 3072, 128;
+    lmr_not_improving: 1024, 0, 3072, 128;
+    lmr_check: 1024, 0, 3072, 128;
+    lmr_capture: 1024, 0, 3072, 128;
+    lmr_refutation: 1024, 0, 3072, 128;
+    lmr_history_divisor: 16384, 4096, 65536, 2048;
+    //share of the remaining clock a move may use at most, and normally
+    time_hard_divisor: 10, 4, 30, 1;
+    time_soft_divisor: 40, 15, 80, 3;
+}
+
+/// SPSA config in OpenBench's format, one `name, int, default, min, max, step, learning rate` line per parameter.
+pub fn openbench_config() -> String {
+    TUNABLES.iter()
+        .map(|t| format!("{}, int, {}, {}, {}, {}, 0.002\n", t.name, t.default, t.min, t.max, t.step))
+        .collect()
+}
+
+/// SPSA config in weather-factory's JSON format.
+pub fn weather_factory_config() -> String {
+    let entries: Vec<String> = TUNABLES.iter()
+        .map(|t| format!("  \"{}\": {{\"value\": {}, \"min_value\": {}, \"max_value\": {}, \"step\": {}}}", t.name, t.default, t.min, t.max, t.step))
+        .collect();
+    format!("{{\n{}\n}}\n", entries.join(",\n"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn defaults_are_in_range_and_configs_list_every_parameter() {
+        for t in TUNABLES {
+            assert!(t.min <= t.default && t.default <= t.max && t.step > 0, "{}", t.name);
+        }
+        assert_eq!(openbench_config().lines().count(), TUNABLES.len());
+        assert_eq!(openbench_config().lines().next(), Some("rfp_margin, int, 90, 40, 200, 10, 0.002"));
+        assert_eq!(weather_factory_config().matches("min_value").count(), TUNABLES.len());
+        assert_eq!(rfp_margin(), TUNABLES[0].default);
+    }
+}
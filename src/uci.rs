@@ -0,0 +1,310 @@
+use cozy_chess::*;
+use crate::error::EngineError;
+use crate::notation::to_san;
+use crate::search::{AlphaBetaSearcher, SearchLimits};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+
+/// A position set up by the front-end: the board to search plus the hashes
+/// of every position since the start, for repetition detection. The start
+/// board and the moves played from it are kept so moves can be taken back.
+#[derive(Clone)]
+pub struct GamePosition {
+    pub board: Board,
+    pub history: Vec<u64>,
+    start: Board,
+    moves: Vec<Move>,
+}
+
+impl Default for GamePosition {
+    fn default() -> Self {
+        GamePosition::new(Board::default())
+    }
+}
+
+impl GamePosition {
+    pub fn new(board: Board) -> Self {
+        GamePosition { history: vec![board.hash()], start: board.clone(), moves: Vec::new(), board }
+    }
+
+    /// Takes back the last move. Returns false if no move has been played.
+    pub fn undo(&mut self) -> bool {
+        if self.moves.pop().is_none() {
+            return false;
+        }
+        self.history.pop();
+        self.board = self.start.clone();
+        for &m in &self.moves {
+            self.board.play(m);
+        }
+        true
+    }
+}
+
+/// Parses `position startpos|fen <fen> [moves ...]`. The result is only
+/// returned if every part is valid, so a bad command never half-applies.
+pub fn parse_position(input: &str) -> Result<GamePosition, EngineError> {
+    let parts: Vec<&str> = input.split_whitespace().collect();
+    let moves_index: usize = parts.iter().position(|&x| x == "moves").unwrap_or(parts.len());
+    let board: Board = match parts.get(1) {
+        Some(&"startpos") => Board::default(),
+        Some(&"fen") => {
+            let fen: String = parts[2..moves_index].join(" ");
+            Board::from_fen(&fen, false).map_err(|_| EngineError::InvalidFen(fen))?
+        },
+        _ => return Err(EngineError::InvalidCommand(input.to_string())),
+    };
+    let mut position: GamePosition = GamePosition::new(board);
+    for m in parts.iter().skip(moves_index + 1) {
+        play_uci_move(&mut position, m)?;
+    }
+    Ok(position)
+}
+
+pub fn play_uci_move(position: &mut GamePosition, m: &str) -> Result<(), EngineError> {
+    match util::parse_uci_move(&position.board, m) {
+        Ok(parsed) if position.board.is_legal(parsed) => {
+            position.board.play(parsed);
+            position.history.push(position.board.hash());
+            position.moves.push(parsed);
+            Ok(())
+        },
+        _ => Err(EngineError::IllegalMove(m.to_string())),
+    }
+}
+
+/// The board with colors swapped and ranks reversed, the same position from the
+/// other side. Evaluation and search should treat both alike.
+pub fn flip_board(board: &Board) -> Board {
+    let original: BoardBuilder = BoardBuilder::from_board(board);
+    let mut flipped: BoardBuilder = BoardBuilder::from_board(board);
+    for square in Square::ALL {
+        *flipped.square_mut(square.flip_rank()) = original.square(square).map(|(piece, color)| (piece, !color));
+    }
+    flipped.castle_rights = [original.castle_rights[1], original.castle_rights[0]];
+    flipped.side_to_move = !original.side_to_move;
+    flipped.en_passant = original.en_passant.map(Square::flip_rank);
+    flipped.build().unwrap()
+}
+
+/// Every legal move, in generation order.
+pub fn legal_moves(board: &Board) -> Vec<Move> {
+    let mut moves: Vec<Move> = Vec::new();
+    board.generate_moves(|p: PieceMoves| {
+        moves.extend(p);
+        false
+    });
+    moves
+}
+
+/// Stockfish-style `d` output: an ASCII board, the FEN, the hash, the checkers and
+/// the number of legal moves.
+pub fn display(board: &Board) -> String {
+    let separator: &str = " +---+---+---+---+---+---+---+---+\n";
+    let mut out: String = String::from(separator);
+    for rank in Rank::ALL.iter().rev() {
+        for file in File::ALL {
+            let square: Square = Square::new(file, *rank);
+            let symbol: char = match (board.piece_on(square), board.color_on(square)) {
+                (Some(piece), Some(Color::White)) => char::from(piece).to_ascii_uppercase(),
+                (Some(piece), _) => char::from(piece),
+                _ => ' ',
+            };
+            out += &format!(" | {}", symbol);
+        }
+        out += &format!(" | {}\n{}", char::from(*rank), separator);
+    }
+    out += "   a   b   c   d   e   f   g   h\n\n";
+    let checkers: Vec<String> = board.checkers().into_iter().map(|s| s.to_string()).collect();
+    out += &format!("Fen: {}\nKey: {:016X}\nCheckers: {}\n", board, board.hash(), checkers.join(" "));
+    out += &format!("Legal moves: {}\n", legal_moves(board).len());
+    out
+}
+
+/// One `<uci> <san>` line per legal move, castling written king to destination.
+pub fn list_moves(board: &Board) -> String {
+    legal_moves(board).into_iter()
+        .map(|m| format!("{} {}\n", AlphaBetaSearcher::to_standard_uci(board, m), to_san(board, m)))
+        .collect()
+}
+
+/// Parses `setoption name <name> [value <value>]`; names may contain spaces.
+pub fn parse_setoption(input: &str) -> Result<(String, String), EngineError> {
+    let words: Vec<&str> = input.split_whitespace().collect();
+    let name_index: usize = words.iter().position(|&w| w == "name").ok_or_else(|| EngineError::InvalidCommand(input.to_string()))?;
+    let value_index: usize = words.iter().position(|&w| w == "value").unwrap_or(words.len());
+    if value_index <= name_index + 1 {
+        return Err(EngineError::InvalidCommand(input.to_string()));
+    }
+    let name: String = words[name_index + 1..value_index].join(" ");
+    let value: String = words.get(value_index + 1..).map_or(String::new(), |v| v.join(" "));
+    Ok((name, value))
+}
+
+/// The fields of `go`; unset ones do not limit the search.
+#[derive(Default)]
+pub struct GoParams {
+    pub wtime: Option<u64>,
+    pub btime: Option<u64>,
+    pub winc: Option<u64>,
+    pub binc: Option<u64>,
+    pub movestogo: Option<u32>,
+    pub movetime: Option<u64>,
+    pub depth: Option<i32>,
+    pub nodes: Option<u64>,
+    pub infinite: bool, //search until `stop`, also set by a `go` without limits
+}
+
+impl GoParams {
+    /// The limits for `side`: its own clock and increment plus the fixed limits,
+    /// none at all when searching until `stop`.
+    pub fn limits(&self, side: Color) -> SearchLimits {
+        if self.infinite {
+            return SearchLimits::default();
+        }
+        let (time_remaining, increment) = match side {
+            Color::White => (self.wtime, self.winc),
+            Color::Black => (self.btime, self.binc),
+        };
+        SearchLimits {
+            time_remaining,
+            increment,
+            moves_to_go: self.movestogo,
+            movetime: self.movetime,
+            depth: self.depth,
+            nodes: self.nodes,
+            ..SearchLimits::default()
+        }
+    }
+}
+
+/// Parses `go`. Unknown tokens are ignored as the UCI spec asks.
+pub fn parse_go(input: &str) -> Result<GoParams, EngineError> {
+    let words: Vec<&str> = input.split_whitespace().collect();
+    let mut params: GoParams = GoParams::default();
+    let mut i: usize = 1;
+    while i < words.len() {
+        match words[i] {
+            "wtime" | "btime" | "winc" | "binc" | "movestogo" | "movetime" | "depth" | "nodes" => {
+                let value: u64 = match words.get(i + 1).map(|v| v.parse::<i64>()) {
+                    //clocks can go negative in some GUIs, treat that as no time left
+                    Some(Ok(value)) => value.max(0) as u64,
+                    Some(Err(_)) => return Err(EngineError::InvalidCommand(format!("invalid number for {}", words[i]))),
+                    None => return Err(EngineError::InvalidCommand(format!("missing value for {}", words[i]))),
+                };
+                match words[i] {
+                    "wtime" => params.wtime = Some(value),
+                    "btime" => params.btime = Some(value),
+                    "winc" => params.winc = Some(value),
+                    "binc" => params.binc = Some(value),
+                    "movestogo" => params.movestogo = Some(value.min(u32::MAX as u64) as u32),
+                    "movetime" => params.movetime = Some(value),
+                    "depth" => params.depth = Some(value.min(i32::MAX as u64) as i32),
+                    _ => params.nodes = Some(value),
+                }
+                i += 2;
+            },
+            "infinite" => {
+                params.infinite = true;
+                i += 1;
+            },
+            _ => i += 1,
+        }
+    }
+    let limited: bool = params.wtime.is_some() || params.btime.is_some() || params.movetime.is_some() || params.depth.is_some() || params.nodes.is_some();
+    params.infinite |= !limited;
+    Ok(params)
+}
+
+/// Reads stdin on a new thread during a `go infinite` search until `stop` or `quit`,
+/// then raises `stop`. The thread returns true when the session should end, on `quit`
+/// or the end of input. Other commands are dropped, as the search cannot take them.
+pub fn watch_for_stop(stop: Arc<AtomicBool>) -> JoinHandle<bool> {
+    std::thread::spawn(move || {
+        let mut input: String = String::new();
+        let quit: bool = loop {
+            input.clear();
+            match std::io::stdin().read_line(&mut input) {
+                Ok(0) | Err(_) => break true,
+                Ok(_) if input.trim() == "stop" => break false,
+                Ok(_) if input.trim() == "quit" => break true,
+                Ok(_) => (),
+            }
+        };
+        stop.store(true, Ordering::Relaxed);
+        quit
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bad_position_is_rejected_whole() {
+        assert!(matches!(parse_position("position startpos moves e2e4 e7e5 e1e3"), Err(EngineError::IllegalMove(_))));
+        assert!(matches!(parse_position("position fen not/a/fen w - - 0 1"), Err(EngineError::InvalidFen(_))));
+        let position: GamePosition = parse_position("position startpos moves e2e4 e7e5 g1f3").unwrap();
+        assert_eq!(position.history.len(), 4);
+        assert_eq!(position.board.side_to_move(), Color::Black);
+    }
+
+    #[test]
+    fn undo_takes_back_moves_and_their_hashes() {
+        let mut position: GamePosition = parse_position("position startpos moves e2e4 e7e5 g1f3").unwrap();
+        assert!(position.undo() && position.undo());
+        assert_eq!(position.board, parse_position("position startpos moves e2e4").unwrap().board);
+        assert_eq!(position.history, vec![Board::default().hash(), position.board.hash()]);
+        assert!(position.undo() && !position.undo());
+        assert_eq!(position.board, Board::default());
+    }
+
+    #[test]
+    fn go_reports_malformed_values() {
+        assert!(parse_go("go wtime abc").is_err());
+        assert!(parse_go("go btime").is_err());
+        assert!(parse_go("go depth -").is_err());
+    }
+
+    #[test]
+    fn go_fills_the_side_to_moves_limits() {
+        let limits: SearchLimits = parse_go("go wtime 1000 btime 2000 winc 10 binc 20 movestogo 5").unwrap().limits(Color::Black);
+        assert_eq!((limits.time_remaining, limits.increment, limits.moves_to_go), (Some(2000), Some(20), Some(5)));
+        let limits: SearchLimits = parse_go("go movetime 500 depth 9 nodes 10000").unwrap().limits(Color::White);
+        assert_eq!((limits.time_remaining, limits.movetime, limits.depth, limits.nodes), (None, Some(500), Some(9), Some(10000)));
+        assert!(parse_go("go infinite").unwrap().infinite);
+        assert!(parse_go("go").unwrap().infinite);
+        assert!(!parse_go("go depth 3").unwrap().infinite);
+        let limits: SearchLimits = parse_go("go infinite wtime 1000").unwrap().limits(Color::White);
+        assert_eq!((limits.time_remaining, limits.depth), (None, None));
+    }
+
+    #[test]
+    fn setoption_names_may_contain_spaces() {
+        assert_eq!(parse_setoption("setoption name Skill Level value 5").unwrap(), ("Skill Level".to_string(), "5".to_string()));
+        assert_eq!(parse_setoption("setoption name Clear Hash").unwrap(), ("Clear Hash".to_string(), String::new()));
+        assert!(parse_setoption("setoption value 5").is_err());
+    }
+
+    #[test]
+    fn flip_swaps_colors_castling_and_en_passant() {
+        let board: Board = "rnbqkb1r/pp1p1ppp/4pn2/2pP4/2P5/8/PP2PPPP/RNBQKBNR w Kq c6 0 4".parse().unwrap();
+        let flipped: Board = flip_board(&board);
+        assert_eq!(flipped.to_string(), "rnbqkbnr/pp2pppp/8/2p5/2Pp4/4PN2/PP1P1PPP/RNBQKB1R b Qk c3 0 4");
+        assert_eq!(flip_board(&flipped), board);
+    }
+
+    #[test]
+    fn display_and_moves_describe_the_board() {
+        let board: Board = "4k3/8/8/8/8/8/4r3/R3K3 w Q - 0 1".parse().unwrap();
+        let shown: String = display(&board);
+        assert!(shown.contains(" | R |   |   |   | K |   |   |   | 1\n"));
+        assert!(shown.contains("Key: "));
+        assert!(shown.contains("Checkers: e2\n"));
+        assert!(shown.contains("Legal moves: 3\n"));
+        let moves: String = list_moves(&board);
+        assert!(moves.contains("e1e2 Kxe2\n"));
+        assert!(!moves.contains("e1c1")); //no castling out of check
+    }
+}
@@ -0,0 +1,357 @@
+//! CECP (XBoard/WinBoard) front-end, entered when the first command is `xboard`.
+//! It drives the same searcher as UCI and keeps the game in a `uci::GamePosition`,
+//! so repetitions and move parsing behave the same under both protocols.
+//!
+//! Input is read on its own thread, so `?` can end a search early and `analyze`
+//! searches each position until the next command arrives.
+use cozy_chess::*;
+use crate::endgame;
+use crate::search::{AlphaBetaSearcher, InfoFormat, SearchLimits};
+use crate::uci::{self, GamePosition};
+use std::io::BufRead;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::sync::Arc;
+
+/// Clock assumed until the GUI sends `level` or `time`: 5 minutes.
+const DEFAULT_CLOCK_MS: u64 = 5 * 60 * 1000;
+
+/// The game and the settings the GUI has sent so far.
+pub struct XboardSession {
+    position: GamePosition,
+    force: bool, //record moves for both sides without thinking
+    analyzing: bool,
+    analysis_done: bool, //the analysis of this position reached its depth limit
+    post: bool, //print thinking output
+    clock_ms: u64, //engine's remaining time, from `level` and then `time`
+    increment_ms: u64,
+    moves_per_session: u32, //moves per time control from `level`, 0 for the whole game
+    movetime_ms: Option<u64>, //fixed time per move from `st`
+    depth: Option<i32>, //depth limit from `sd`
+}
+
+impl XboardSession {
+    pub fn new() -> Self {
+        XboardSession {
+            position: GamePosition::default(),
+            force: false,
+            analyzing: false,
+            analysis_done: false,
+            post: false,
+            clock_ms: DEFAULT_CLOCK_MS,
+            increment_ms: 0,
+            moves_per_session: 0,
+            movetime_ms: None,
+            depth: None,
+        }
+    }
+
+    /// Handles one command. Returns false on `quit`.
+    pub fn handle(&mut self, searcher: &mut AlphaBetaSearcher, input: &str) -> bool {
+        let words: Vec<&str> = input.split_whitespace().collect();
+        let Some(&command) = words.first() else {
+            return true;
+        };
+        let argument: Option<&str> = words.get(1).copied();
+        self.analysis_done = false;
+        match command {
+            "quit" => return false,
+            "protover" => {
+                println!("feature done=0");
+                println!("feature myname=\"slinky_chess\" ping=1 setboard=1 usermove=1 time=1 draw=0 sigint=0 sigterm=0");
+                println!("feature reuse=1 analyze=1 colors=0 playother=0 san=0 memory=1");
+                println!("feature done=1");
+            },
+            "new" => {
+                self.position = GamePosition::default();
+                self.force = false;
+                self.depth = None;
+                searcher.new_game();
+            },
+            "force" | "result" => self.force = true,
+            "go" => {
+                self.force = false;
+                self.think(searcher);
+            },
+            "post" => self.post = true,
+            "nopost" => self.post = false,
+            "level" => match parse_level(&words[1..]) {
+                Some((moves_per_session, base, increment)) => {
+                    self.moves_per_session = moves_per_session;
+                    self.clock_ms = base;
+                    self.increment_ms = increment;
+                    self.movetime_ms = None;
+                },
+                None => println!("Error (bad level): {}", input),
+            },
+            "st" => match argument.and_then(|s| s.parse::<f64>().ok()) {
+                Some(seconds) => self.movetime_ms = Some((seconds * 1000.0) as u64),
+                None => println!("Error (bad st): {}", input),
+            },
+            "sd" => match argument.and_then(|d| d.parse().ok()) {
+                Some(depth) => self.depth = Some(depth),
+                None => println!("Error (bad sd): {}", input),
+            },
+            //clocks come in centiseconds and may be negative once flagged
+            "time" => self.clock_ms = argument.and_then(|t| t.parse::<i64>().ok()).map_or(self.clock_ms, |t| t.max(0) as u64 * 10),
+            "otim" => (),
+            "usermove" => match argument {
+                Some(m) => self.user_move(searcher, m),
+                None => println!("Error (missing move): {}", input),
+            },
+            "undo" | "remove" => {
+                let plies: usize = if command == "undo" { 1 } else { 2 };
+                for _ in 0..plies {
+                    self.position.undo();
+                }
+            },
+            "setboard" => match Board::from_fen(&words[1..].join(" "), false) {
+                Ok(board) => self.position = GamePosition::new(board),
+                Err(_) => println!("tellusererror Illegal position"),
+            },
+            "analyze" => {
+                self.analyzing = true;
+                self.force = true;
+            },
+            "exit" => self.analyzing = false,
+            "ping" => println!("pong {}", argument.unwrap_or("")),
+            "memory" => match argument.and_then(|mb| mb.parse().ok()) {
+                Some(size_mb) => {
+                    searcher.set_hash_size(size_mb);
+                    searcher.allocate_hash();
+                },
+                None => println!("Error (bad memory): {}", input),
+            },
+            "xboard" | "accepted" | "rejected" | "random" | "hard" | "easy" | "computer" | "name" | "?" | "." | "draw" | "hint" | "bk" => (),
+            //old GUIs send moves without `usermove`
+            _ if uci::play_uci_move(&mut self.position.clone(), command).is_ok() => self.user_move(searcher, command),
+            _ => println!("Error (unknown command): {}", command),
+        }
+        true
+    }
+
+    fn user_move(&mut self, searcher: &mut AlphaBetaSearcher, m: &str) {
+        if uci::play_uci_move(&mut self.position, m).is_err() {
+            println!("Illegal move: {}", m);
+            return;
+        }
+        if !self.analyzing && !self.force && game_result(&self.position).is_none() {
+            self.think(searcher);
+        }
+    }
+
+    /// Searches the side to move's reply and plays it.
+    fn think(&mut self, searcher: &mut AlphaBetaSearcher) {
+        if let Some(result) = game_result(&self.position) {
+            println!("{}", result);
+            return;
+        }
+        self.prepare(searcher, self.post);
+        let best_move: String = searcher.get_best_move(&self.position.board, self.limits());
+        if uci::play_uci_move(&mut self.position, &best_move).is_err() {
+            return;
+        }
+        println!("move {}", best_move);
+        if let Some(result) = game_result(&self.position) {
+            println!("{}", result);
+        }
+    }
+
+    /// Limits for the engine's move: `st`, or the clock with the moves left before
+    /// the next time control when `level` set one.
+    fn limits(&self) -> SearchLimits {
+        if let Some(movetime) = self.movetime_ms {
+            return SearchLimits { movetime: Some(movetime), depth: self.depth, ..SearchLimits::default() };
+        }
+        //each side has made half the plies played since the game started
+        let played: u32 = (self.position.history.len() as u32 - 1) / 2;
+        SearchLimits {
+            time_remaining: Some(self.clock_ms),
+            increment: Some(self.increment_ms),
+            moves_to_go: (self.moves_per_session > 0).then(|| self.moves_per_session - played % self.moves_per_session),
+            depth: self.depth,
+            ..SearchLimits::default()
+        }
+    }
+
+    fn wants_analysis(&self) -> bool {
+        self.analyzing && !self.analysis_done && game_result(&self.position).is_none()
+    }
+
+    /// Searches the current position until the searcher's stop signal is set or the
+    /// `sd` depth is reached.
+    fn analyze(&mut self, searcher: &mut AlphaBetaSearcher) {
+        self.prepare(searcher, true);
+        searcher.search(&self.position.board, SearchLimits { depth: self.depth, ..SearchLimits::default() });
+    }
+
+    /// Loads the game's repetition history and the output settings into `searcher`.
+    fn prepare(&self, searcher: &mut AlphaBetaSearcher, post: bool) {
+        searcher.clear_threefold_repetition();
+        for hash in &self.position.history {
+            searcher.add_to_threefold_repetition(*hash);
+        }
+        searcher.set_verbose(post);
+        searcher.set_info_format(InfoFormat::Xboard);
+    }
+}
+
+/// Parses the base time of `level`, `minutes` or `minutes:seconds`, into ms.
+fn parse_base_time(base: &str) -> Option<u64> {
+    let (minutes, seconds) = base.split_once(':').unwrap_or((base, "0"));
+    Some((minutes.parse::<u64>().ok()? * 60 + seconds.parse::<u64>().ok()?) * 1000)
+}
+
+/// Parses `level MPS BASE INC` into moves per session, base time in ms and increment
+/// in ms. The increment is in seconds and may have a fraction.
+fn parse_level(arguments: &[&str]) -> Option<(u32, u64, u64)> {
+    let [moves_per_session, base, increment] = arguments else {
+        return None;
+    };
+    let increment: f64 = increment.parse().ok().filter(|&i: &f64| i >= 0.0)?;
+    Some((moves_per_session.parse().ok()?, parse_base_time(base)?, (increment * 1000.0) as u64))
+}
+
+/// The result command for a finished game, or `None` while it goes on.
+fn game_result(position: &GamePosition) -> Option<&'static str> {
+    let board: &Board = &position.board;
+    let repetitions: usize = position.history.iter().filter(|&&hash| hash == board.hash()).count();
+    match board.status() {
+        GameStatus::Won if board.side_to_move() == Color::White => Some("0-1 {Black mates}"),
+        GameStatus::Won => Some("1-0 {White mates}"),
+        GameStatus::Drawn if board.checkers().is_empty() && uci::legal_moves(board).is_empty() => Some("1/2-1/2 {Stalemate}"),
+        GameStatus::Drawn => Some("1/2-1/2 {50 move rule}"),
+        GameStatus::Ongoing if repetitions >= 3 => Some("1/2-1/2 {Draw by repetition}"),
+        GameStatus::Ongoing => None,
+    }
+}
+
+/// Reads stdin on its own thread. `?` stops the running search, and while `analyzing`
+/// is set any command does, so it can be handled.
+fn read_commands(stop: Arc<AtomicBool>, analyzing: Arc<AtomicBool>) -> Receiver<String> {
+    let (sender, receiver) = mpsc::channel();
+    std::thread::spawn(move || {
+        for line in std::io::stdin().lock().lines() {
+            let line: String = match line {
+                Ok(line) => line,
+                Err(e) => {
+                    println!("telluser failed to read input: {}", e);
+                    break;
+                }
+            };
+            if analyzing.load(Ordering::Relaxed) || line.trim() == "?" {
+                stop.store(true, Ordering::Relaxed);
+            }
+            if sender.send(line).is_err() {
+                break;
+            }
+        }
+    });
+    receiver
+}
+
+/// Runs the protocol on stdin until `quit` or end of input.
+pub fn run(searcher: &mut AlphaBetaSearcher) {
+    //XBoard has no isready, so set up what UCI defers to it now
+    searcher.allocate_hash();
+    endgame::init_bitbases();
+    let stop: Arc<AtomicBool> = searcher.stop_signal();
+    let analyzing: Arc<AtomicBool> = Arc::new(AtomicBool::new(false));
+    let commands: Receiver<String> = read_commands(Arc::clone(&stop), Arc::clone(&analyzing));
+    let mut session: XboardSession = XboardSession::new();
+    loop {
+        //commands that arrive after the signal is cleared stop the analysis below
+        analyzing.store(session.wants_analysis(), Ordering::Relaxed);
+        stop.store(false, Ordering::Relaxed);
+        let input: String = match commands.try_recv() {
+            Ok(input) => input,
+            Err(TryRecvError::Empty) if session.wants_analysis() => {
+                session.analyze(searcher);
+                session.analysis_done = !stop.load(Ordering::Relaxed);
+                continue;
+            },
+            Err(TryRecvError::Empty) => match commands.recv() {
+                Ok(input) => input,
+                Err(_) => break,
+            },
+            Err(TryRecvError::Disconnected) => break,
+        };
+        analyzing.store(false, Ordering::Relaxed);
+        if !session.handle(searcher, input.trim()) {
+            break;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn session_with(searcher: &mut AlphaBetaSearcher, commands: &[&str]) -> XboardSession {
+        let mut session: XboardSession = XboardSession::new();
+        for command in commands {
+            assert!(session.handle(searcher, command));
+        }
+        session
+    }
+
+    #[test]
+    fn force_mode_records_moves_and_undo_takes_them_back() {
+        let mut searcher: AlphaBetaSearcher = AlphaBetaSearcher::new();
+        let mut session: XboardSession = session_with(&mut searcher, &["new", "force", "usermove e2e4", "e7e5", "usermove g1f3", "usermove e1e3"]);
+        assert_eq!(session.position.history.len(), 4);
+        session.handle(&mut searcher, "remove");
+        assert_eq!(session.position.board, uci::parse_position("position startpos moves e2e4").unwrap().board);
+        assert!(!session.handle(&mut searcher, "quit"));
+    }
+
+    #[test]
+    fn engine_replies_to_a_user_move_within_the_limits() {
+        let mut searcher: AlphaBetaSearcher = AlphaBetaSearcher::new();
+        searcher.set_hash_size(1);
+        let session: XboardSession = session_with(&mut searcher, &["new", "level 40 0:30 0", "sd 2", "usermove e2e4"]);
+        assert_eq!((session.clock_ms, session.depth), (30000, Some(2)));
+        assert_eq!(session.position.board.side_to_move(), Color::White);
+        assert_eq!(session.position.history.len(), 3);
+    }
+
+    #[test]
+    fn finished_games_report_their_result() {
+        let mate: GamePosition = uci::parse_position("position startpos moves f2f3 e7e5 g2g4 d8h4").unwrap();
+        assert_eq!(game_result(&mate), Some("0-1 {Black mates}"));
+        let stalemate: GamePosition = GamePosition::new(Board::from_fen("7k/5Q2/6K1/8/8/8/8/8 b - - 0 1", false).unwrap());
+        assert_eq!(game_result(&stalemate), Some("1/2-1/2 {Stalemate}"));
+        assert_eq!(game_result(&GamePosition::default()), None);
+        assert_eq!(parse_base_time("2:30"), Some(150000));
+        assert_eq!(parse_base_time("x"), None);
+    }
+
+    #[test]
+    fn level_keeps_moves_per_session_and_increment() {
+        let mut searcher: AlphaBetaSearcher = AlphaBetaSearcher::new();
+        let mut session: XboardSession = session_with(&mut searcher, &["new", "force", "level 40 5 0.5", "e2e4", "e7e5"]);
+        let limits: SearchLimits = session.limits();
+        assert_eq!((limits.time_remaining, limits.increment, limits.moves_to_go), (Some(300000), Some(500), Some(39)));
+        session.handle(&mut searcher, "level 0 2:30 12");
+        assert_eq!((session.limits().increment, session.limits().moves_to_go), (Some(12000), None));
+        assert_eq!(parse_level(&["40", "5"]), None);
+    }
+
+    #[test]
+    fn analysis_runs_until_stopped() {
+        let mut searcher: AlphaBetaSearcher = AlphaBetaSearcher::new();
+        searcher.set_hash_size(1);
+        let mut session: XboardSession = session_with(&mut searcher, &["new", "analyze", "e2e4"]);
+        assert!(session.wants_analysis());
+        let stop: Arc<AtomicBool> = searcher.stop_signal();
+        let stopper = std::thread::spawn(move || {
+            std::thread::sleep(std::time::Duration::from_millis(200));
+            stop.store(true, Ordering::Relaxed);
+        });
+        session.analyze(&mut searcher);
+        stopper.join().unwrap();
+        session.handle(&mut searcher, "exit");
+        assert!(!session.wants_analysis());
+        assert_eq!(session.position.history.len(), 2);
+    }
+}